@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+/// Samples per FFT. At 44.1 kHz this resolves about 21 Hz per bin.
+const FFT_SIZE: usize = 2048;
+
+/// Analyses per second
+const ANALYSIS_RATE: u32 = 60;
+
+/// Amount of frequency bands and the range they cover in Hz
+const BAND_COUNT: usize = 16;
+const LOWEST_FREQUENCY: f32 = 40.0;
+const HIGHEST_FREQUENCY: f32 = 16000.0;
+
+/// Frequencies that are used for the beat detection
+const BASS_RANGE: (f32, f32) = (40.0, 150.0);
+
+/// Levels in dB that are shown as silence and as full level
+const SILENCE_DB: f32 = -60.0;
+const FULL_DB: f32 = 0.0;
+
+/// A beat is detected if the bass energy exceeds its recent average by this factor
+const BEAT_THRESHOLD: f32 = 1.5;
+
+/// Analyses over which the average bass energy is taken, about one second
+const BEAT_HISTORY: usize = ANALYSIS_RATE as usize;
+
+/// Analyses that have to pass between two beats
+const MIN_BEAT_INTERVAL: u32 = 8;
+
+/// Bass energy below this is considered silence and never a beat
+const MIN_BEAT_ENERGY: f32 = 1e-4;
+
+/// Result of analysing a piece of audio
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    /// Loudness between 0 and 1
+    pub level: f32,
+    /// Level of each frequency band between 0 and 1, starting with the lowest frequencies
+    pub bands: Vec<f32>,
+    /// Whether a beat started
+    pub beat: bool,
+}
+
+/// Measures loudness, frequency bands and beats of an audio stream
+pub struct Analyzer {
+    sample_rate: u32,
+    sensitivity: f32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// The latest FFT_SIZE samples
+    samples: VecDeque<f32>,
+    spectrum: Vec<Complex<f32>>,
+    /// Range of FFT bins of each band
+    bands: Vec<(usize, usize)>,
+    bass: (usize, usize),
+    bass_history: VecDeque<f32>,
+    since_beat: u32,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32, sensitivity: f32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+
+        // Hann window
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        // Bands are spaced logarithmically like the perceived pitch
+        let highest = HIGHEST_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let ratio = (highest / LOWEST_FREQUENCY).powf(1.0 / BAND_COUNT as f32);
+        let bands = (0..BAND_COUNT)
+            .map(|band| {
+                let low = LOWEST_FREQUENCY * ratio.powi(band as i32);
+                Self::bins(sample_rate, (low, low * ratio))
+            })
+            .collect();
+
+        Analyzer {
+            sample_rate,
+            // Negative or NaN sensitivities would turn every level into 0 or NaN
+            sensitivity: sensitivity.max(0.0),
+            fft,
+            window,
+            samples: VecDeque::from(vec![0.0; FFT_SIZE]),
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            bands,
+            bass: Self::bins(sample_rate, BASS_RANGE),
+            bass_history: VecDeque::with_capacity(BEAT_HISTORY),
+            since_beat: MIN_BEAT_INTERVAL,
+        }
+    }
+
+    /// Amount of new samples per analysis
+    pub fn hop_size(&self) -> usize {
+        (self.sample_rate / ANALYSIS_RATE).max(1) as usize
+    }
+
+    /// Analyse the next samples together with the preceding ones
+    pub fn process(&mut self, samples: &[f32]) -> Features {
+        for sample in samples {
+            self.samples.pop_front();
+            self.samples.push_back(*sample);
+        }
+
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+        // A full scale sine has an RMS of -3 dB
+        let level = self.to_level(10.0 * mean_square.log10() + 3.0);
+
+        for ((bin, sample), weight) in self
+            .spectrum
+            .iter_mut()
+            .zip(self.samples.iter())
+            .zip(self.window.iter())
+        {
+            *bin = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(&mut self.spectrum);
+
+        let bands = self
+            .bands
+            .iter()
+            .map(|range| {
+                let magnitude = self.magnitude(*range);
+                self.to_level(20.0 * magnitude.log10())
+            })
+            .collect();
+
+        Features {
+            level,
+            bands,
+            beat: self.detect_beat(),
+        }
+    }
+
+    /// Combined magnitude of a range of bins. A full scale sine has a magnitude of about 1.
+    fn magnitude(&self, (first, last): (usize, usize)) -> f32 {
+        let energy: f32 = self.spectrum[first..=last]
+            .iter()
+            .map(|bin| bin.norm_sqr())
+            .sum();
+        energy.sqrt() / (FFT_SIZE as f32 / 4.0)
+    }
+
+    /// Compares the energy of the bass to its recent average
+    fn detect_beat(&mut self) -> bool {
+        let energy = self.magnitude(self.bass).powi(2);
+        let average = if self.bass_history.is_empty() {
+            f32::MAX
+        } else {
+            self.bass_history.iter().sum::<f32>() / self.bass_history.len() as f32
+        };
+
+        if self.bass_history.len() == BEAT_HISTORY {
+            self.bass_history.pop_front();
+        }
+        self.bass_history.push_back(energy);
+
+        self.since_beat = self.since_beat.saturating_add(1);
+        let beat = energy > MIN_BEAT_ENERGY
+            && energy > average * BEAT_THRESHOLD
+            && self.since_beat >= MIN_BEAT_INTERVAL;
+        if beat {
+            self.since_beat = 0;
+        }
+        beat
+    }
+
+    /// Maps dB to a level between 0 and 1
+    fn to_level(&self, db: f32) -> f32 {
+        // Also catches -inf of silence, which multiplied by a sensitivity of 0 is NaN
+        if db <= SILENCE_DB {
+            return 0.0;
+        }
+        ((db - SILENCE_DB) / (FULL_DB - SILENCE_DB) * self.sensitivity).clamp(0.0, 1.0)
+    }
+
+    /// First and last FFT bin of a frequency range. Every range has at least one bin.
+    fn bins(sample_rate: u32, (low, high): (f32, f32)) -> (usize, usize) {
+        let resolution = sample_rate as f32 / FFT_SIZE as f32;
+        let first = ((low / resolution).round() as usize).clamp(1, FFT_SIZE / 2);
+        let last = ((high / resolution).round() as usize).clamp(first + 1, FFT_SIZE / 2 + 1) - 1;
+        (first, last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn sine(frequency: f32, amplitude: f32, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// Analyses the samples in pieces of the hop size like the audio thread
+    fn analyze(analyzer: &mut Analyzer, samples: &[f32]) -> Vec<Features> {
+        let hop_size = analyzer.hop_size();
+        samples
+            .chunks(hop_size)
+            .map(|chunk| analyzer.process(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn silence_has_no_level() {
+        for sensitivity in [0.0, 1.0, -1.0] {
+            let mut analyzer = Analyzer::new(SAMPLE_RATE, sensitivity);
+            let features = analyzer.process(&vec![0.0; analyzer.hop_size()]);
+            assert_eq!(features.level, 0.0);
+            assert_eq!(features.bands, vec![0.0; BAND_COUNT]);
+            assert!(!features.beat);
+        }
+    }
+
+    #[test]
+    fn full_scale_sine_has_full_level() {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE, 1.0);
+        let features = analyze(&mut analyzer, &sine(1000.0, 1.0, FFT_SIZE));
+        let last = features.last().unwrap();
+        assert!(last.level > 0.95, "level {}", last.level);
+    }
+
+    #[test]
+    fn sensitivity_scales_the_level() {
+        let samples = sine(1000.0, 0.01, FFT_SIZE);
+        let quiet = analyze(&mut Analyzer::new(SAMPLE_RATE, 1.0), &samples);
+        let sensitive = analyze(&mut Analyzer::new(SAMPLE_RATE, 2.0), &samples);
+        let (quiet, sensitive) = (quiet.last().unwrap(), sensitive.last().unwrap());
+        assert!(quiet.level > 0.0);
+        assert!((sensitive.level - 2.0 * quiet.level).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tone_is_shown_in_its_band() {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE, 1.0);
+        let features = analyze(&mut analyzer, &sine(1000.0, 0.5, FFT_SIZE));
+        let bands = &features.last().unwrap().bands;
+
+        let bin = Analyzer::bins(SAMPLE_RATE, (1000.0, 1000.0)).0;
+        let expected = analyzer
+            .bands
+            .iter()
+            .position(|(first, last)| (*first..=*last).contains(&bin))
+            .unwrap();
+        let loudest = (0..bands.len())
+            .max_by(|a, b| bands[*a].total_cmp(&bands[*b]))
+            .unwrap();
+        assert_eq!(loudest, expected);
+    }
+
+    #[test]
+    fn bass_after_silence_is_a_beat() {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE, 1.0);
+        let silence = analyze(&mut analyzer, &vec![0.0; SAMPLE_RATE as usize / 2]);
+        assert!(silence.iter().all(|features| !features.beat));
+
+        let bass = analyze(&mut analyzer, &sine(80.0, 0.8, SAMPLE_RATE as usize / 10));
+        assert!(bass.iter().any(|features| features.beat));
+    }
+
+    #[test]
+    fn steady_bass_is_not_a_beat() {
+        let mut analyzer = Analyzer::new(SAMPLE_RATE, 1.0);
+        let features = analyze(&mut analyzer, &sine(80.0, 0.8, 2 * SAMPLE_RATE as usize));
+        let second_half = &features[features.len() / 2..];
+        assert!(second_half.iter().all(|features| !features.beat));
+    }
+}
@@ -0,0 +1,237 @@
+mod analyzer;
+mod modulation;
+mod source;
+mod visualizer;
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tracing::{debug, info};
+
+use crate::effects::Layout;
+use crate::output::{Output, OutputMessage};
+use crate::settings::{AudioSettings, Settings, Visualization};
+use crate::source::LedSource;
+
+use analyzer::Analyzer;
+pub use analyzer::Features;
+pub use modulation::Modulator;
+pub use source::list_devices;
+use source::AudioSource;
+use visualizer::Visualizer;
+
+/// How long the visualizer waits for an analysis before it returns to handle the commands, so
+/// they are not blocked by a silent or stalled input
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Handle to the audio thread. The audio thread reads the input and sends the features of every
+/// analysis.
+pub struct Audio {
+    receiver: Receiver<Features>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Audio {
+    /// Open the audio input and start analysing it
+    pub fn spawn(settings: &AudioSettings) -> Result<Self> {
+        // Open the input before starting the thread so errors are reported right away
+        let source = source::open(settings)?;
+        Ok(Self::start(source, settings.sensitivity))
+    }
+
+    fn start(source: Box<dyn AudioSource>, sensitivity: f32) -> Self {
+        let analyzer = Analyzer::new(source.sample_rate(), sensitivity);
+
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || Self::run(source, analyzer, sender));
+
+        Audio {
+            receiver,
+            handle: Some(handle),
+        }
+    }
+
+    /// Wait up to the timeout for the next analysis. Returns None if none arrived in time or at
+    /// the end of the input, and the error of the audio thread.
+    pub fn recv(&mut self, timeout: Duration) -> Result<Option<Features>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(features) => Ok(Some(features)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => self.join().map(|_| None),
+        }
+    }
+
+    /// Whether the input has ended and every analysis has been received
+    pub fn ended(&self) -> bool {
+        self.handle.is_none()
+    }
+
+    /// The newest analysis that arrived since the last call, without waiting. Beats of the
+    /// skipped analyses are kept. After the end of the input None is returned.
+    pub fn latest(&mut self) -> Result<Option<Features>> {
+        let mut latest: Option<Features> = None;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(mut features) => {
+                    features.beat |= latest.as_ref().is_some_and(|previous| previous.beat);
+                    latest = Some(features);
+                }
+                Err(TryRecvError::Empty) => return Ok(latest),
+                Err(TryRecvError::Disconnected) => {
+                    self.join()?;
+                    return Ok(latest);
+                }
+            }
+        }
+    }
+
+    /// Wait for the stopped audio thread and return its error
+    fn join(&mut self) -> Result<()> {
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(anyhow!("Audio thread panicked")),
+            None => Ok(()),
+        }
+    }
+
+    fn run(
+        mut source: Box<dyn AudioSource>,
+        mut analyzer: Analyzer,
+        sender: Sender<Features>,
+    ) -> Result<()> {
+        let mut samples = vec![0.0; analyzer.hop_size()];
+
+        loop {
+            let count = source.read(&mut samples)?;
+            if count == 0 {
+                info!("Audio input ended");
+                return Ok(());
+            }
+
+            if sender.send(analyzer.process(&samples[..count])).is_err() {
+                debug!("Audio channel closed. Stopping audio thread");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Shows the audio visualization instead of the video
+pub struct AudioVisualizer {
+    settings: AudioSettings,
+    audio: Audio,
+    visualizer: Visualizer,
+    enabled: bool,
+}
+
+impl AudioVisualizer {
+    pub fn new(settings: &Settings) -> Result<Self> {
+        Ok(AudioVisualizer {
+            settings: settings.audio.clone(),
+            audio: Audio::spawn(&settings.audio)?,
+            visualizer: Visualizer::new(settings.audio.visualization, &Layout::new(settings)),
+            enabled: true,
+        })
+    }
+}
+
+impl LedSource for AudioVisualizer {
+    fn process(&mut self, output: &mut Output) -> Result<bool> {
+        let Some(features) = self.audio.recv(RECV_TIMEOUT)? else {
+            return Ok(!self.audio.ended());
+        };
+        if !self.enabled {
+            return Ok(true);
+        }
+
+        output.send(OutputMessage::Frame(self.visualizer.render(&features)))?;
+
+        // Flashes are shown right away instead of being smoothed
+        if features.beat && self.settings.visualization == Visualization::BeatFlash {
+            output.send(OutputMessage::SceneCut)?;
+        }
+        Ok(true)
+    }
+
+    fn reconfigure(&mut self, settings: &Settings) -> Result<()> {
+        // The input is only opened again if it changed
+        if settings.audio != self.settings {
+            self.audio = Audio::spawn(&settings.audio)?;
+        }
+        self.visualizer = Visualizer::new(settings.audio.visualization, &Layout::new(settings));
+        self.settings = settings.audio.clone();
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, enabled: bool, output: &mut Output) -> Result<()> {
+        info!(
+            "Audio visualizer {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.enabled = enabled;
+        output.send(OutputMessage::Visible(enabled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Input that delivers a number of silent hops and then either ends or stalls
+    struct Silence {
+        hops: usize,
+        stall: bool,
+    }
+
+    impl AudioSource for Silence {
+        fn sample_rate(&self) -> u32 {
+            8000
+        }
+
+        fn read(&mut self, buffer: &mut [f32]) -> Result<usize> {
+            if self.hops == 0 {
+                if self.stall {
+                    thread::sleep(Duration::from_secs(1));
+                }
+                return Ok(0);
+            }
+            self.hops -= 1;
+            buffer.fill(0.0);
+            Ok(buffer.len())
+        }
+    }
+
+    #[test]
+    fn recv_returns_every_analysis_and_the_end() {
+        let mut audio = Audio::start(
+            Box::new(Silence {
+                hops: 3,
+                stall: false,
+            }),
+            1.0,
+        );
+        for _ in 0..3 {
+            assert!(audio.recv(Duration::from_secs(5)).unwrap().is_some());
+        }
+        assert!(audio.recv(Duration::from_secs(5)).unwrap().is_none());
+        assert!(audio.ended());
+    }
+
+    #[test]
+    fn recv_does_not_wait_for_a_stalled_input() {
+        let mut audio = Audio::start(
+            Box::new(Silence {
+                hops: 0,
+                stall: true,
+            }),
+            1.0,
+        );
+        let started = Instant::now();
+        assert!(audio.recv(Duration::from_millis(10)).unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(!audio.ended());
+    }
+}
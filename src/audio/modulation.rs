@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use smart_leds::RGB16;
+
+use super::Features;
+use crate::color;
+use crate::settings::HybridSettings;
+
+/// Rec. 709 luma coefficients for red, green and blue
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Modulates the video colors with the audio. Beats make the LEDs pulse brighter and loud
+/// passages boost the saturation.
+pub struct Modulator {
+    settings: HybridSettings,
+    /// Strength of the current beat pulse between 0 and 1
+    pulse: f32,
+    /// Latest loudness between 0 and 1
+    level: f32,
+    last_update: Option<Instant>,
+}
+
+impl Modulator {
+    pub fn new(settings: HybridSettings) -> Self {
+        Modulator {
+            settings,
+            pulse: 0.0,
+            level: 0.0,
+            last_update: None,
+        }
+    }
+
+    /// Let the pulse fade and take the latest analysis if there is one
+    pub fn update(&mut self, features: Option<&Features>, now: Instant) {
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+
+        let decay = Duration::from_millis(self.settings.pulse_decay_ms).as_secs_f32();
+        self.pulse = if decay > 0.0 {
+            self.pulse * (-elapsed.as_secs_f32() / decay).exp()
+        } else {
+            0.0
+        };
+
+        if let Some(features) = features {
+            if features.beat {
+                self.pulse = 1.0;
+            }
+            self.level = features.level;
+        }
+    }
+
+    /// Modulate the LED colors in place
+    pub fn apply(&self, leds: &mut [RGB16]) {
+        let brightness = 1.0 + self.settings.beat_pulse * self.pulse;
+        let saturation = 1.0 + self.settings.saturation_boost * self.level;
+
+        for led in leds.iter_mut() {
+            let mut rgb = color::to_unit(*led);
+            let luma: f32 = rgb.iter().zip(LUMA.iter()).map(|(c, l)| c * l).sum();
+            for value in rgb.iter_mut() {
+                *value = (luma + (*value - luma) * saturation) * brightness;
+            }
+            *led = color::from_unit(rgb);
+        }
+    }
+}
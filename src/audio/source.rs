@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use hound::{SampleFormat, WavReader};
+use tracing::info;
+
+use crate::settings::{AudioInput, AudioSettings, Settings};
+
+/// Delivers mono audio samples in the range -1..1
+pub trait AudioSource: Send {
+    fn sample_rate(&self) -> u32;
+
+    /// Fill the buffer with the next samples. Returns how many samples have been read, 0 at the
+    /// end of the input.
+    fn read(&mut self, buffer: &mut [f32]) -> Result<usize>;
+}
+
+/// Open the configured audio input
+pub fn open(settings: &AudioSettings) -> Result<Box<dyn AudioSource>> {
+    let rate = settings.sample_rate.to_string();
+    let channels = settings.channels.to_string();
+
+    let source: Box<dyn AudioSource> = match settings.input {
+        AudioInput::Stdin => Box::new(PcmStream::new(
+            Box::new(io::stdin()),
+            None,
+            settings.sample_rate,
+            settings.channels,
+        )),
+        AudioInput::PulseAudio => {
+            let device = settings.device.as_deref().unwrap_or("@DEFAULT_MONITOR@");
+            let args = [
+                "--format=s16le",
+                &format!("--rate={}", rate),
+                &format!("--channels={}", channels),
+                &format!("--device={}", device),
+            ];
+            PcmStream::record("parec", &args, settings)?
+        }
+        AudioInput::Alsa => {
+            let mut args = vec![
+                "-q", "-t", "raw", "-f", "S16_LE", "-r", &rate, "-c", &channels,
+            ];
+            if let Some(device) = &settings.device {
+                args.extend(["-D", device.as_str()]);
+            }
+            PcmStream::record("arecord", &args, settings)?
+        }
+        AudioInput::File => {
+            let path = settings
+                .path
+                .as_ref()
+                .ok_or_else(|| anyhow!("The file input needs a path to a WAV file"))?;
+            Box::new(WavFile::open(&Settings::resolve_path(path))?)
+        }
+    };
+
+    info!(
+        "Reading audio from {:?} at {} Hz",
+        settings.input,
+        source.sample_rate()
+    );
+    Ok(source)
+}
+
+/// Devices that can be used as Settings.audio.device together with their input. Inputs whose
+/// tools are not installed are skipped.
+pub fn list_devices() -> Vec<(AudioInput, String)> {
+    let mut devices = Vec::new();
+
+    // Columns: index, name, driver, format, state
+    if let Some(sources) = run("pactl", &["list", "short", "sources"]) {
+        devices.extend(
+            sources
+                .lines()
+                .filter_map(|line| line.split('\t').nth(1))
+                .map(|name| (AudioInput::PulseAudio, name.to_string())),
+        );
+    }
+
+    // Lines like "card 1: Device [USB Audio Device], device 0: USB Audio [USB Audio]"
+    if let Some(cards) = run("arecord", &["-l"]) {
+        for line in cards.lines() {
+            let Some(rest) = line.strip_prefix("card ") else {
+                continue;
+            };
+            let Some((card, rest)) = rest.split_once(':') else {
+                continue;
+            };
+            let Some((name, rest)) = rest.split_once(", device ") else {
+                continue;
+            };
+            let Some((device, _)) = rest.split_once(':') else {
+                continue;
+            };
+            let device = format!("hw:{},{} ({})", card, device, name.trim());
+            devices.push((AudioInput::Alsa, device));
+        }
+    }
+    devices
+}
+
+/// Output of a program or None if it could not be run
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Raw signed 16 bit little endian PCM with interleaved channels
+struct PcmStream {
+    reader: BufReader<Box<dyn Read + Send>>,
+    /// Recording process that writes to the reader
+    child: Option<Child>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl PcmStream {
+    fn new(
+        reader: Box<dyn Read + Send>,
+        child: Option<Child>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Self {
+        PcmStream {
+            reader: BufReader::new(reader),
+            child,
+            sample_rate,
+            channels: channels.max(1),
+        }
+    }
+
+    /// Start a recording program that writes raw PCM to stdout
+    fn record(program: &str, args: &[&str], settings: &AudioSettings) -> Result<Box<Self>> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start {}", program))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Could not read the output of {}", program))?;
+
+        Ok(Box::new(Self::new(
+            Box::new(stdout),
+            Some(child),
+            settings.sample_rate,
+            settings.channels,
+        )))
+    }
+}
+
+impl AudioSource for PcmStream {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buffer: &mut [f32]) -> Result<usize> {
+        let mut frame = vec![0u8; self.channels as usize * 2];
+
+        for (count, sample) in buffer.iter_mut().enumerate() {
+            match self.reader.read_exact(&mut frame) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(count),
+                Err(e) => return Err(e.into()),
+            }
+
+            // Mix all channels down to mono
+            let sum: f32 = frame
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0)
+                .sum();
+            *sample = sum / self.channels as f32;
+        }
+        Ok(buffer.len())
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// WAV file that is read at the speed it would be played
+struct WavFile {
+    reader: WavReader<BufReader<File>>,
+    started: Option<Instant>,
+    /// Samples per channel that have been read
+    position: u64,
+}
+
+impl WavFile {
+    fn open(path: &Path) -> Result<Self> {
+        let reader =
+            WavReader::open(path).with_context(|| format!("Could not open WAV file {:?}", path))?;
+        let spec = reader.spec();
+        info!(
+            "Playing {:?} with {} channels and {} bit samples",
+            path, spec.channels, spec.bits_per_sample
+        );
+        Ok(WavFile {
+            reader,
+            started: None,
+            position: 0,
+        })
+    }
+
+    /// Next sample of one channel
+    fn next_sample(&mut self) -> Option<Result<f32>> {
+        let spec = self.reader.spec();
+        match spec.sample_format {
+            SampleFormat::Float => self
+                .reader
+                .samples::<f32>()
+                .next()
+                .map(|sample| sample.map_err(|e| e.into())),
+            SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                self.reader
+                    .samples::<i32>()
+                    .next()
+                    .map(|sample| Ok(sample? as f32 / scale))
+            }
+        }
+    }
+}
+
+impl AudioSource for WavFile {
+    fn sample_rate(&self) -> u32 {
+        self.reader.spec().sample_rate
+    }
+
+    fn read(&mut self, buffer: &mut [f32]) -> Result<usize> {
+        let channels = self.reader.spec().channels.max(1);
+        let started = *self.started.get_or_insert_with(Instant::now);
+
+        let mut count = 0;
+        'samples: for sample in buffer.iter_mut() {
+            let mut sum = 0.0;
+            for _ in 0..channels {
+                match self.next_sample() {
+                    Some(value) => sum += value?,
+                    None => break 'samples,
+                }
+            }
+            *sample = sum / channels as f32;
+            count += 1;
+        }
+        self.position += count as u64;
+
+        // Wait until the samples would have been played
+        let played = Duration::from_secs_f64(self.position as f64 / self.sample_rate() as f64);
+        if let Some(remaining) = played.checked_sub(started.elapsed()) {
+            sleep(remaining);
+        }
+        Ok(count)
+    }
+}
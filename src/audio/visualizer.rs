@@ -0,0 +1,172 @@
+use std::f32::consts::PI;
+
+use smart_leds::RGB16;
+
+use super::analyzer::Features;
+use crate::color;
+use crate::effects::Layout;
+use crate::settings::Visualization;
+
+/// Brightness that remains of a beat flash after each frame
+const FLASH_DECAY: f32 = 0.85;
+
+/// Hue of the loudest part of the VU meter (red) and the quiet part (green)
+const VU_HUES: (f32, f32) = (0.0, 1.0 / 3.0);
+
+/// Maps the audio features onto the LEDs around the screen
+pub struct Visualizer {
+    visualization: Visualization,
+    /// Distance of every LED from the nearest bottom corner along the border, between 0 and 1
+    from_bottom: Vec<f32>,
+    /// Angle of every LED around the screen center, 0 at the bottom center, 1 at the top center
+    angles: Vec<f32>,
+    flash: f32,
+    flash_hue: f32,
+}
+
+impl Visualizer {
+    pub fn new(visualization: Visualization, layout: &Layout) -> Self {
+        let (width, height) = (layout.width, layout.height);
+
+        let from_bottom = layout
+            .positions
+            .iter()
+            .map(|[x, y]| {
+                let across = x.min(1.0 - x) * width;
+                let distance = if *y >= 1.0 {
+                    across
+                } else if *y <= 0.0 {
+                    height + across
+                } else {
+                    (1.0 - y) * height
+                };
+                distance / (height + width / 2.0)
+            })
+            .collect();
+
+        let angles = layout
+            .positions
+            .iter()
+            .map(|[x, y]| ((x - 0.5) * width).atan2((y - 0.5) * height).abs() / PI)
+            .collect();
+
+        Visualizer {
+            visualization,
+            from_bottom,
+            angles,
+            flash: 0.0,
+            flash_hue: 0.0,
+        }
+    }
+
+    pub fn render(&mut self, features: &Features) -> Vec<RGB16> {
+        let leds: Vec<[f32; 3]> = match self.visualization {
+            Visualization::Spectrum => self.spectrum(features),
+            Visualization::VuMeter => self.vu_meter(features),
+            Visualization::BeatFlash => self.beat_flash(features),
+        };
+        leds.into_iter().map(color::from_unit).collect()
+    }
+
+    /// Each band is shown at its angle with a hue from red for the bass to violet for the treble
+    fn spectrum(&self, features: &Features) -> Vec<[f32; 3]> {
+        let band_count = features.bands.len();
+        self.angles
+            .iter()
+            .map(|angle| {
+                let band = ((angle * band_count as f32) as usize).min(band_count.saturating_sub(1));
+                let level = features.bands.get(band).copied().unwrap_or_default();
+                color::from_hsv(0.8 * band as f32 / band_count as f32, 1.0, level)
+            })
+            .collect()
+    }
+
+    /// LEDs light up from the bottom corners to the top center with the loudness
+    fn vu_meter(&self, features: &Features) -> Vec<[f32; 3]> {
+        self.from_bottom
+            .iter()
+            .map(|distance| {
+                if *distance > features.level {
+                    return [0.0; 3];
+                }
+                let hue = VU_HUES.1 + (VU_HUES.0 - VU_HUES.1) * distance;
+                color::from_hsv(hue, 1.0, 1.0)
+            })
+            .collect()
+    }
+
+    /// The whole strip flashes in a new color on every beat and glows with the loudness
+    fn beat_flash(&mut self, features: &Features) -> Vec<[f32; 3]> {
+        if features.beat {
+            self.flash = 1.0;
+            self.flash_hue = (self.flash_hue + 0.15).fract();
+        } else {
+            self.flash *= FLASH_DECAY;
+        }
+
+        let value = self.flash.max(features.level * 0.2);
+        vec![color::from_hsv(self.flash_hue, 1.0, value); self.angles.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LEDs at the bottom left corner, the middle of the left edge, the top center and the
+    /// bottom center of a square screen
+    fn layout() -> Layout {
+        Layout {
+            width: 100.0,
+            height: 100.0,
+            positions: vec![[0.0, 1.0], [0.0, 0.5], [0.5, 0.0], [0.5, 1.0]],
+        }
+    }
+
+    fn features(level: f32, bands: Vec<f32>, beat: bool) -> Features {
+        Features { level, bands, beat }
+    }
+
+    fn is_lit(led: &RGB16) -> bool {
+        led.r > 0 || led.g > 0 || led.b > 0
+    }
+
+    #[test]
+    fn vu_meter_fills_from_the_bottom_corners() {
+        let mut visualizer = Visualizer::new(Visualization::VuMeter, &layout());
+
+        let leds = visualizer.render(&features(0.5, vec![], false));
+        assert_eq!(
+            leds.iter().map(is_lit).collect::<Vec<_>>(),
+            [true, true, false, true]
+        );
+
+        let leds = visualizer.render(&features(1.0, vec![], false));
+        assert!(leds.iter().all(is_lit));
+    }
+
+    #[test]
+    fn spectrum_shows_the_bass_at_the_bottom() {
+        let mut visualizer = Visualizer::new(Visualization::Spectrum, &layout());
+        let leds = visualizer.render(&features(1.0, vec![1.0, 0.0, 0.0, 0.0], false));
+        assert!(is_lit(&leds[3]));
+        assert!(!is_lit(&leds[1]));
+        assert!(!is_lit(&leds[2]));
+
+        let leds = visualizer.render(&features(1.0, vec![0.0, 0.0, 0.0, 1.0], false));
+        assert!(is_lit(&leds[2]));
+        assert!(!is_lit(&leds[3]));
+    }
+
+    #[test]
+    fn beat_flash_lights_everything_and_decays() {
+        let mut visualizer = Visualizer::new(Visualization::BeatFlash, &layout());
+        let flash = visualizer.render(&features(0.0, vec![], true));
+        assert!(flash.iter().all(|led| *led == flash[0] && is_lit(led)));
+
+        let decayed = visualizer.render(&features(0.0, vec![], false));
+        let brightness = |led: &RGB16| led.r as u32 + led.g as u32 + led.b as u32;
+        assert!(brightness(&decayed[0]) < brightness(&flash[0]));
+        assert!(is_lit(&decayed[0]));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+
+#[path = "../socket_path.rs"]
+mod socket_path;
+
+/// Control a running rustylight through its control socket. The socket has to be enabled with a
+/// [socket] section in the settings.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path of the control socket [default: $XDG_RUNTIME_DIR/rustylight.sock]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Turn the LEDs on
+    On,
+    /// Turn the LEDs off
+    Off,
+    /// Set the brightness in percent
+    Brightness { percent: f32 },
+    /// Show a static color given as hex, e.g. ff8800
+    Color { color: String },
+    /// Show an effect, e.g. rainbow, or "ambilight" to show the captured colors again
+    Effect { name: String },
+    /// Print the current state as JSON
+    Status,
+}
+
+impl Command {
+    /// Request in the JSON lines protocol of the control socket
+    fn request(&self) -> Value {
+        match self {
+            Command::On => json!({ "command": "on" }),
+            Command::Off => json!({ "command": "off" }),
+            Command::Brightness { percent } => json!({ "command": "brightness", "value": percent }),
+            Command::Color { color } => json!({ "command": "color", "value": color }),
+            Command::Effect { name } => json!({ "command": "effect", "value": name }),
+            Command::Status => json!({ "command": "status" }),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let socket = cli.socket.unwrap_or_else(socket_path::default_path);
+
+    let mut stream = UnixStream::connect(&socket).with_context(|| {
+        format!(
+            "Could not connect to {:?}. Is rustylight running with the control socket enabled?",
+            socket
+        )
+    })?;
+    writeln!(stream, "{}", cli.command.request())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line).context("Invalid response")?;
+
+    if response["ok"] != Value::Bool(true) {
+        let error = response["error"].as_str().unwrap_or("Unknown error");
+        bail!("{}", error);
+    }
+    if let Command::Status = cli.command {
+        let state = response
+            .get("state")
+            .ok_or_else(|| anyhow!("The response contains no state"))?;
+        println!("{}", serde_json::to_string_pretty(state)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+#[cfg(feature = "highgui")]
+use opencv::highgui;
+use opencv::{
+    core::{Size, Vec3b},
+    prelude::*,
+    videoio::VideoCapture,
+};
+use tracing::{debug, info};
+
+use crate::audio::{Audio, Modulator};
+use crate::color_space::ColorSpaceCorrection;
+use crate::output::{Output, OutputMessage};
+use crate::perspective::PerspectiveCorrection;
+use crate::preview::Preview;
+use crate::resampler;
+use crate::scene_cut::SceneCutDetector;
+use crate::settings::{Mode, Settings};
+use crate::signal_detector::{SignalDetector, SignalState};
+use crate::source::LedSource;
+use crate::tone_mapping::ToneMapper;
+use crate::translation_engine::{Action, TranslationEngine};
+use crate::video::Video;
+
+/// How long a disabled capture waits before checking for commands again
+const DISABLED_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the running capture waits for a valid frame before the main loop gets the chance to
+/// handle commands, e.g. while the grabber is unplugged
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a newly opened input may take to deliver its first frame
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait between reads of frames with size zero
+const INVALID_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Read frames until one with a valid size arrives. Returns false if there was none within the
+/// timeout.
+fn wait_for_frame(v: &mut VideoCapture, f: &mut Mat, timeout: Duration) -> Result<bool> {
+    let start = Instant::now();
+    loop {
+        let read = v.read(f)?;
+        let size = f.size()?;
+        if read && size.width > 0 && size.height > 0 {
+            return Ok(true);
+        }
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        debug!("Input with invalid size. Waiting...");
+        sleep(INVALID_FRAME_INTERVAL);
+    }
+}
+
+/// Turns the frames of the video input into LED colors
+pub struct Capture {
+    /// Settings the capture has been set up with
+    settings: Settings,
+    input: VideoCapture,
+    frame: Mat,
+    warped_frame: Mat,
+    stages: Stages,
+    /// In hybrid mode the audio modulates the video colors
+    audio: Option<(Audio, Modulator)>,
+    enabled: bool,
+    preview: Preview,
+    last_snapshot: Option<Instant>,
+}
+
+impl Capture {
+    /// Open the video input and wait for the first frame
+    pub fn new(settings: &Settings, preview: Preview) -> Result<Self> {
+        let (input, frame) = Self::open(settings)?;
+        let stages = Stages::new(settings, frame.size()?)?;
+        let audio = Self::audio(settings)?;
+
+        Ok(Capture {
+            settings: settings.clone(),
+            input,
+            frame,
+            warped_frame: Mat::default(),
+            stages,
+            audio,
+            enabled: true,
+            preview,
+            last_snapshot: None,
+        })
+    }
+
+    /// Open the video input and return its first frame
+    pub fn snapshot(settings: &Settings) -> Result<Mat> {
+        let (_, frame) = Self::open(settings)?;
+        Ok(frame)
+    }
+
+    fn open(settings: &Settings) -> Result<(VideoCapture, Mat)> {
+        let mut input = Video::new(settings)?;
+        let mut frame = Mat::default();
+
+        // Get the size of the video feed
+        if !wait_for_frame(&mut input, &mut frame, OPEN_TIMEOUT)? {
+            bail!(
+                "The video input delivered no frame within {:?}",
+                OPEN_TIMEOUT
+            );
+        }
+        let size = frame.size()?;
+        info!(
+            "Reading video data with resolution widht: {}, height: {}",
+            size.width, size.height
+        );
+        Ok((input, frame))
+    }
+
+    /// Set up everything for the new settings and only replace the current state if nothing
+    /// failed
+    fn prepare(&mut self, settings: &Settings, reopen: bool) -> Result<()> {
+        let input = if reopen {
+            Some(Self::open(settings)?)
+        } else {
+            None
+        };
+
+        let size = match &input {
+            Some((_, frame)) => frame.size()?,
+            None => self.frame.size()?,
+        };
+        let stages = Stages::new(settings, size)?;
+
+        let restart_audio =
+            settings.mode != self.settings.mode || settings.audio != self.settings.audio;
+        let audio = if restart_audio {
+            Some(Self::audio(settings)?)
+        } else {
+            None
+        };
+
+        if let Some((input, frame)) = input {
+            self.input = input;
+            self.frame = frame;
+        }
+        match audio {
+            Some(audio) => self.audio = audio,
+            None => {
+                if let Some((_, modulator)) = &mut self.audio {
+                    *modulator = Modulator::new(settings.hybrid);
+                }
+            }
+        }
+        self.stages = stages;
+        self.settings = settings.clone();
+        Ok(())
+    }
+
+    fn audio(settings: &Settings) -> Result<Option<(Audio, Modulator)>> {
+        match settings.mode {
+            Mode::Hybrid => Ok(Some((
+                Audio::spawn(&settings.audio)?,
+                Modulator::new(settings.hybrid),
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl LedSource for Capture {
+    fn process(&mut self, output: &mut Output) -> Result<bool> {
+        if !self.enabled {
+            sleep(DISABLED_INTERVAL);
+            return Ok(true);
+        }
+
+        if !wait_for_frame(&mut self.input, &mut self.frame, FRAME_TIMEOUT)? {
+            return Ok(true);
+        }
+
+        let stages = &mut self.stages;
+        let frame = match &stages.perspective {
+            Some(correction) => {
+                correction.apply(&self.frame, &mut self.warped_frame)?;
+                &self.warped_frame
+            }
+            None => &self.frame,
+        };
+
+        // Downscaled snapshot for the web preview
+        if self.preview.is_watched() {
+            let interval = Duration::from_secs_f64(1.0 / self.settings.preview.fps.max(0.1));
+            let due = match self.last_snapshot {
+                Some(last) => last.elapsed() >= interval,
+                None => true,
+            };
+            if due {
+                self.preview.set_snapshot(frame, &self.settings)?;
+                self.last_snapshot = Some(Instant::now());
+            }
+        }
+
+        if let Some(state) = stages.signal_detector.update(frame, Instant::now())? {
+            output.send(OutputMessage::Visible(state == SignalState::Active))?;
+        }
+
+        for func in stages.translation_funcs.iter() {
+            func(frame, &mut stages.border)?;
+        }
+
+        if stages.color_space.is_active() {
+            stages.color_space.apply(&mut stages.border);
+        }
+
+        if let Some(tone_mapper) = &stages.tone_mapper {
+            tone_mapper.apply(&mut stages.border);
+        }
+
+        let mut leds = resampler::resample(&stages.border, self.settings.led_count as usize);
+
+        let mut beat = false;
+        if let Some((audio, modulator)) = &mut self.audio {
+            let features = audio.latest()?;
+            beat = features.as_ref().is_some_and(|features| features.beat);
+            modulator.update(features.as_ref(), Instant::now());
+            modulator.apply(&mut leds);
+        }
+
+        output.send(OutputMessage::Frame(leds))?;
+
+        // Hard cuts and beat pulses bypass the smoothing
+        let scene_cut = stages
+            .scene_cut_detector
+            .as_mut()
+            .is_some_and(|detector| detector.update(&stages.border));
+        if scene_cut || beat {
+            output.send(OutputMessage::SceneCut)?;
+        }
+
+        #[cfg(feature = "highgui")]
+        {
+            highgui::imshow("original", &self.frame)?;
+            highgui::imshow("frame", frame)?;
+
+            let key = highgui::wait_key(1)?;
+            if key == 113 {
+                // quit with q
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn reconfigure(&mut self, settings: &Settings) -> Result<()> {
+        // The input is only opened again if it changed. The old one has to be released first
+        // because most devices can only be opened once.
+        let reopen = settings.video_device != self.settings.video_device
+            || settings.processing_resolution != self.settings.processing_resolution;
+        if reopen {
+            self.input.release()?;
+        }
+
+        match self.prepare(settings, reopen) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if reopen {
+                    let (input, frame) = Self::open(&self.settings)?;
+                    self.input = input;
+                    self.frame = frame;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool, output: &mut Output) -> Result<()> {
+        info!("Capture {}", if enabled { "enabled" } else { "disabled" });
+        self.enabled = enabled;
+        let active = self.stages.signal_detector.state() == SignalState::Active;
+        output.send(OutputMessage::Visible(enabled && active))
+    }
+
+    fn release(&mut self) -> Result<()> {
+        info!("Releasing video input");
+        self.input.release()?;
+        Ok(())
+    }
+}
+
+/// Processing of each frame that depends on the settings and the frame size
+struct Stages {
+    perspective: Option<PerspectiveCorrection>,
+    /// Translation funcs that shall be applied to each frame
+    translation_funcs: [Action; 4],
+    /// Colors along the border of the frame. They will be resampled to exactly led_count LEDs.
+    border: Vec<Vec3b>,
+    color_space: ColorSpaceCorrection,
+    tone_mapper: Option<ToneMapper>,
+    /// Turns the LEDs off if there is no signal or the picture does not change
+    signal_detector: SignalDetector,
+    /// Hard cuts bypass the smoothing
+    scene_cut_detector: Option<SceneCutDetector>,
+}
+
+impl Stages {
+    fn new(settings: &Settings, size: Size) -> Result<Self> {
+        // The filmed screen will be warped to a rectangle of the same size as the camera image
+        let perspective = match settings.capture_quad {
+            Some(quad) => Some(PerspectiveCorrection::new(&quad, size)?),
+            None => None,
+        };
+
+        // Resolve the capture depth of each edge for the actual frame size
+        let depths = settings.capture_area_size.resolve(size.width, size.height);
+        info!("Capture depths in pixels: {:?}", depths);
+
+        // Opposing edges must not overlap
+        if !depths.fits(size.width, size.height) {
+            bail!(
+                "Border is too thick! The following must hold: left + right < width && top + bottom < height"
+            );
+        }
+
+        // Amount of pixels along the border. They will be resampled to exactly led_count LEDs.
+        let border_length = depths.border_length(size.width, size.height);
+        let pixel_per_led = border_length as f64 / settings.led_count as f64;
+        info!("Pixels per LED: {:.2}", pixel_per_led);
+
+        let translation_funcs = TranslationEngine::new(
+            settings.start_corner,
+            settings.direction,
+            size.width,
+            size.height,
+            depths,
+            settings.sampling_mode.resolve(),
+        );
+
+        // Processing that depends on the video device
+        let input_profile = settings.input_profile();
+        debug!("Using input profile {:?}", input_profile);
+
+        Ok(Stages {
+            perspective,
+            translation_funcs,
+            border: vec![Vec3b::default(); border_length as usize],
+            color_space: ColorSpaceCorrection::new(&input_profile),
+            tone_mapper: input_profile.tone_mapping.map(ToneMapper::new),
+            signal_detector: SignalDetector::new(settings.auto_off.clone())?,
+            scene_cut_detector: settings
+                .smoothing
+                .scene_cut_threshold
+                .map(SceneCutDetector::new),
+        })
+    }
+}
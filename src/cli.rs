@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use crate::settings::{LogLevel, OutputDevice, Settings};
+
+/// Ambilight for the Raspberry Pi. Without a command the ambilight is started.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Settings file to use instead of ~/.config/rustylight/settings.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Log level instead of the one in the settings
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LogLevel>,
+
+    /// Index of the video device instead of the one in the settings
+    #[arg(long, global = true, value_name = "DEVICE")]
+    pub input: Option<i32>,
+
+    /// Where the LED colors are written to instead of the output in the settings
+    #[arg(long, global = true, value_name = "OUTPUT")]
+    pub output: Option<OutputDevice>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the ambilight
+    Run,
+    /// Check the settings file without starting anything
+    CheckConfig,
+    /// Print the default settings. They can be used as a starting point for a settings file.
+    PrintDefaultConfig,
+    /// List the video and audio devices that can be used as input
+    ListDevices,
+    /// Show the edges and the direction of the lightstrip to check the layout settings
+    TestPattern {
+        /// Stop after this many seconds instead of running until interrupted
+        #[arg(long, value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+    /// Save a frame of the video input
+    Snapshot {
+        /// Image file to write. The format follows from the extension.
+        #[arg(default_value = "snapshot.png")]
+        path: PathBuf,
+    },
+    /// Detect the corners of the filmed screen in a frame of the video input and save them to the
+    /// settings. The screen should show a bright, evenly lit picture.
+    Calibrate,
+    /// Turn all LEDs off
+    Off,
+}
+
+impl Cli {
+    /// Read the settings file and apply the command line overrides
+    pub fn load_settings(&self) -> Result<Settings> {
+        let mut settings = Settings::load()?;
+        self.apply(&mut settings);
+        Ok(settings)
+    }
+
+    /// Replace the settings with the values given on the command line
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(log_level) = self.log_level {
+            settings.log_level = log_level;
+        }
+        if let Some(input) = self.input {
+            settings.video_device = input;
+        }
+        if let Some(output) = self.output {
+            settings.output = output;
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use smart_leds::{RGB16, RGB8};
+
+/// Maximum value of a channel of the internal 16 bit colors
+pub const MAX: f32 = u16::MAX as f32;
+
+/// Factor between 8 bit and 16 bit channel values (255 * 257 = 65535)
+const SCALE_8_TO_16: u16 = 257;
+
+/// Widen an 8 bit color to the internal 16 bit precision
+pub fn from_rgb8(color: RGB8) -> RGB16 {
+    RGB16 {
+        r: color.r as u16 * SCALE_8_TO_16,
+        g: color.g as u16 * SCALE_8_TO_16,
+        b: color.b as u16 * SCALE_8_TO_16,
+    }
+}
+
+/// Reduce a 16 bit color to 8 bit by rounding each channel
+pub fn to_rgb8(color: RGB16) -> RGB8 {
+    let channel =
+        |value: u16| ((value as u32 + SCALE_8_TO_16 as u32 / 2) / SCALE_8_TO_16 as u32) as u8;
+    RGB8 {
+        r: channel(color.r),
+        g: channel(color.g),
+        b: channel(color.b),
+    }
+}
+
+/// Channels of a 16 bit color as floats in the range 0..1
+pub fn to_unit(color: RGB16) -> [f32; 3] {
+    [color.r, color.g, color.b].map(|value| value as f32 / MAX)
+}
+
+/// 16 bit color from channels in the range 0..1. Values outside are clamped.
+pub fn from_unit(color: [f32; 3]) -> RGB16 {
+    let channel = |value: f32| (value * MAX).round().clamp(0.0, MAX) as u16;
+    RGB16 {
+        r: channel(color[0]),
+        g: channel(color[1]),
+        b: channel(color[2]),
+    }
+}
+
+/// Converts a color given as hue (0..1, wrapping), saturation and value (both 0..1) to RGB
+/// channels in the range 0..1
+pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    let hue = hue.rem_euclid(1.0) * 6.0;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let m = value - chroma;
+
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+/// Converts a color with channels in the range 0..1 to hue, saturation and value. Hue is in the
+/// range 0..1 as well.
+pub fn to_hsv([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    if chroma <= 0.0 {
+        return (0.0, 0.0, max);
+    }
+
+    let hue = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    (hue / 6.0, chroma / max, max)
+}
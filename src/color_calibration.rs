@@ -0,0 +1,110 @@
+use smart_leds::RGB16;
+
+use crate::color;
+use crate::settings::ColorCalibrationSettings;
+
+/// Rec. 709 luma coefficients for red, green and blue
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Applies the color calibration to the LED colors before they are written to the lightstrip.
+/// Saturation mixes the channels, black level, gain and gamma are applied to each channel on its
+/// own. The brightness is applied last so dimming does not push dark colors below the black level.
+pub struct ColorCalibration {
+    settings: ColorCalibrationSettings,
+}
+
+impl ColorCalibration {
+    pub fn new(settings: ColorCalibrationSettings) -> Self {
+        ColorCalibration { settings }
+    }
+
+    pub fn settings(&self) -> ColorCalibrationSettings {
+        self.settings
+    }
+
+    /// Calibrate all LEDs in place
+    pub fn apply(&self, leds: &mut [RGB16]) {
+        for led in leds.iter_mut() {
+            *led = self.apply_single(*led);
+        }
+    }
+
+    fn apply_single(&self, led: RGB16) -> RGB16 {
+        let mut color = color::to_unit(led);
+
+        // Move each channel away from or towards the luma of the color
+        let luma: f32 = color.iter().zip(LUMA.iter()).map(|(c, l)| c * l).sum();
+        for (channel, value) in color.iter_mut().enumerate() {
+            let saturated = (luma + (*value - luma) * self.settings.saturation).clamp(0.0, 1.0);
+            let curved = self.channel_curve(channel, saturated);
+            *value = (curved * self.settings.brightness).clamp(0.0, 1.0);
+        }
+
+        color::from_unit(color)
+    }
+
+    /// Black level, gain and gamma of a single channel value in the range 0..1
+    fn channel_curve(&self, channel: usize, value: f32) -> f32 {
+        let black_level = self.settings.black_level as f32 / 255.0;
+        if value <= black_level {
+            return 0.0;
+        }
+
+        // Stretch the remaining range back to 0..1
+        let normalized = (value - black_level) / (1.0 - black_level);
+        normalized.powf(self.settings.gamma[channel].max(0.01)) * self.settings.gain[channel]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_leds::RGB8;
+
+    fn calibration(black_level: u8, brightness: f32) -> ColorCalibration {
+        ColorCalibration::new(ColorCalibrationSettings {
+            black_level,
+            brightness,
+            ..Default::default()
+        })
+    }
+
+    fn gray(value: u8) -> RGB16 {
+        color::from_rgb8(RGB8::new(value, value, value))
+    }
+
+    #[test]
+    fn defaults_keep_the_color() {
+        let led = RGB16 {
+            r: 1000,
+            g: 30000,
+            b: 65535,
+        };
+        assert_eq!(calibration(0, 1.0).apply_single(led), led);
+    }
+
+    #[test]
+    fn black_level_turns_dark_colors_off() {
+        assert_eq!(
+            calibration(20, 1.0).apply_single(gray(20)),
+            RGB16::default()
+        );
+        assert_ne!(
+            calibration(20, 1.0).apply_single(gray(40)),
+            RGB16::default()
+        );
+    }
+
+    #[test]
+    fn dimmed_colors_above_the_black_level_stay_lit() {
+        let led = calibration(20, 0.1).apply_single(gray(40));
+        assert!(led.r > 0 && led.g > 0 && led.b > 0);
+    }
+
+    #[test]
+    fn brightness_scales_the_calibrated_color() {
+        let full = calibration(20, 1.0).apply_single(gray(200));
+        let dimmed = calibration(20, 0.5).apply_single(gray(200));
+        assert!((dimmed.r as i32 - full.r as i32 / 2).abs() <= 1);
+    }
+}
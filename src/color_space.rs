@@ -0,0 +1,165 @@
+use opencv::core::Vec3b;
+use tracing::info;
+
+use crate::settings::{ColorRange, InputProfile, MatrixCorrection};
+
+/// Lowest and highest value of limited range video
+const LIMITED_BLACK: u8 = 16;
+const LIMITED_WHITE: u8 = 235;
+
+/// Amount of frames over which the black level is observed before the range is decided
+const DETECTION_WINDOW: u32 = 250;
+
+/// Darkest values that are still considered limited range black. Values below can only occur in
+/// full range video.
+const DETECTION_LIMITED_BLACK: std::ops::RangeInclusive<u8> = 14..=20;
+
+/// Luma coefficients (Kr, Kb) of the YUV matrices
+const BT601: (f32, f32) = (0.299, 0.114);
+const BT709: (f32, f32) = (0.2126, 0.0722);
+
+/// Corrects the color space of the captured border colors. Limited range video is expanded to
+/// the full range and colors that have been decoded with the wrong YUV matrix are converted.
+pub struct ColorSpaceCorrection {
+    range: ColorRange,
+    matrix: MatrixCorrection,
+    detector: RangeDetector,
+    /// Expands limited range values to full range
+    expand: [u8; 256],
+}
+
+impl ColorSpaceCorrection {
+    pub fn new(profile: &InputProfile) -> Self {
+        let mut expand = [0u8; 256];
+        for (value, expanded) in expand.iter_mut().enumerate() {
+            let scaled = (value as f32 - LIMITED_BLACK as f32) * 255.0
+                / (LIMITED_WHITE - LIMITED_BLACK) as f32;
+            *expanded = scaled.round().clamp(0.0, 255.0) as u8;
+        }
+
+        ColorSpaceCorrection {
+            range: profile.color_range,
+            matrix: profile.matrix_correction,
+            detector: RangeDetector::new(),
+            expand,
+        }
+    }
+
+    /// Whether any correction will be applied
+    pub fn is_active(&self) -> bool {
+        !matches!(
+            (self.range, self.matrix),
+            (ColorRange::Full, MatrixCorrection::Off)
+        )
+    }
+
+    /// Correct the sampled border colors in place
+    pub fn apply(&mut self, border: &mut [Vec3b]) {
+        let limited = match self.range {
+            ColorRange::Full => false,
+            ColorRange::Limited => true,
+            ColorRange::Auto => self.detector.observe(border),
+        };
+
+        if limited {
+            for pixel in border.iter_mut() {
+                for channel in 0..3 {
+                    pixel[channel] = self.expand[pixel[channel] as usize];
+                }
+            }
+        }
+
+        let (decoded, encoded) = match self.matrix {
+            MatrixCorrection::Off => return,
+            MatrixCorrection::Bt601ToBt709 => (BT601, BT709),
+            MatrixCorrection::Bt709ToBt601 => (BT709, BT601),
+        };
+
+        for pixel in border.iter_mut() {
+            // Vec3b holds BGR
+            let rgb = [pixel[2], pixel[1], pixel[0]].map(|value| value as f32 / 255.0);
+
+            // Undo the decoding of the capture device and decode with the correct matrix
+            let corrected = from_ycbcr(encoded, to_ycbcr(decoded, rgb));
+
+            let channel = |value: f32| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+            *pixel = Vec3b::from_array([
+                channel(corrected[2]),
+                channel(corrected[1]),
+                channel(corrected[0]),
+            ]);
+        }
+    }
+}
+
+/// Detects limited range video from the observed black levels. Limited range video never gets
+/// darker than 16, so if dark content has been seen but nothing darker than that, the video is
+/// most likely limited range.
+struct RangeDetector {
+    limited: bool,
+    window_min: u8,
+    frames: u32,
+}
+
+impl RangeDetector {
+    fn new() -> Self {
+        RangeDetector {
+            limited: false,
+            window_min: u8::MAX,
+            frames: 0,
+        }
+    }
+
+    /// Observe the colors of a frame and return whether the video is currently considered
+    /// limited range
+    fn observe(&mut self, border: &[Vec3b]) -> bool {
+        let frame_min = border
+            .iter()
+            .map(|pixel| pixel[0].min(pixel[1]).min(pixel[2]))
+            .min()
+            .unwrap_or(u8::MAX);
+        self.window_min = self.window_min.min(frame_min);
+        self.frames += 1;
+
+        if self.frames >= DETECTION_WINDOW {
+            // Without dark content in the window the previous decision is kept
+            let limited = if self.window_min < *DETECTION_LIMITED_BLACK.start() {
+                false
+            } else if DETECTION_LIMITED_BLACK.contains(&self.window_min) {
+                true
+            } else {
+                self.limited
+            };
+
+            if limited != self.limited {
+                info!(
+                    "Detected {} range video (darkest value: {})",
+                    if limited { "limited" } else { "full" },
+                    self.window_min
+                );
+                self.limited = limited;
+            }
+
+            self.window_min = u8::MAX;
+            self.frames = 0;
+        }
+
+        self.limited
+    }
+}
+
+/// Converts RGB to YCbCr with the given luma coefficients
+fn to_ycbcr((kr, kb): (f32, f32), [r, g, b]: [f32; 3]) -> [f32; 3] {
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    let cb = (b - y) / (2.0 * (1.0 - kb));
+    let cr = (r - y) / (2.0 * (1.0 - kr));
+    [y, cb, cr]
+}
+
+/// Converts YCbCr to RGB with the given luma coefficients
+fn from_ycbcr((kr, kb): (f32, f32), [y, cb, cr]: [f32; 3]) -> [f32; 3] {
+    let r = y + 2.0 * (1.0 - kr) * cr;
+    let b = y + 2.0 * (1.0 - kb) * cb;
+    let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+    [r, g, b]
+}
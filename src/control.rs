@@ -0,0 +1,103 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::settings::{EffectSettings, Settings};
+
+/// How long a remote interface waits for the main loop to apply new settings
+const APPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests of the remote interfaces. The main loop handles them between frames.
+pub enum Command {
+    /// Turn the LEDs on or off
+    SetEnabled(bool),
+    /// Overall brightness between 0 and 1
+    SetBrightness(f32),
+    /// Show an effect on top of the capture or stop it
+    SetEffect(Option<EffectSettings>),
+    /// Replace the settings. The result is sent back once they have been applied.
+    ApplySettings(Box<Settings>, Sender<Result<()>>),
+    /// Turn the LEDs off and stop rustylight
+    Shutdown,
+}
+
+/// What the main loop is currently doing
+#[derive(Debug, Clone, Serialize)]
+pub struct State {
+    pub enabled: bool,
+    pub brightness: f32,
+    pub effect: Option<EffectSettings>,
+    #[serde(skip)]
+    pub settings: Settings,
+}
+
+/// Connection of the remote interfaces to the main loop. It can be cloned for every interface.
+#[derive(Clone)]
+pub struct Control {
+    sender: Sender<Command>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Control {
+    /// Create the control and the receiver for the main loop
+    pub fn new(settings: &Settings) -> (Self, Receiver<Command>) {
+        let (sender, receiver) = mpsc::channel();
+        let state = State {
+            enabled: true,
+            brightness: settings.color_calibration.brightness,
+            effect: None,
+            settings: settings.clone(),
+        };
+
+        let control = Control {
+            sender,
+            state: Arc::new(Mutex::new(state)),
+        };
+        (control, receiver)
+    }
+
+    pub fn state(&self) -> State {
+        self.lock().clone()
+    }
+
+    /// Change the state. Only used by the main loop after it handled a command.
+    pub fn update(&self, change: impl FnOnce(&mut State)) {
+        change(&mut self.lock());
+    }
+
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.sender
+            .send(command)
+            .map_err(|_| anyhow!("Rustylight is shutting down"))
+    }
+
+    /// Let the main loop apply new settings and wait for the result
+    pub fn apply_settings(&self, settings: Settings) -> Result<()> {
+        let (reply, result) = mpsc::channel();
+        self.send(Command::ApplySettings(Box::new(settings), reply))?;
+        result
+            .recv_timeout(APPLY_TIMEOUT)
+            .map_err(|_| anyhow!("The settings have not been applied in time"))?
+    }
+
+    /// Read the settings with reload and let the main loop apply them. Settings that can not be
+    /// read are rejected, settings that can not be applied are reported by the main loop. In both
+    /// cases the current settings stay active.
+    pub fn reload_settings(&self, reload: impl FnOnce() -> Result<Settings>) {
+        match reload() {
+            Ok(settings) => {
+                let _ = self.apply_settings(settings);
+            }
+            Err(e) => warn!("Keeping the current settings: {:#}", e),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state stays usable even if a thread panicked while holding the lock
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
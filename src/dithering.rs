@@ -0,0 +1,45 @@
+use smart_leds::{RGB16, RGB8};
+
+/// Difference between two neighbouring 8 bit values in 16 bit precision
+const STEP: i32 = 257;
+
+/// Temporal dithering from the internal 16 bit colors to the 8 bit LEDs. The rounding error of
+/// each channel is carried over to the next frame, so on average over a few frames the LEDs show
+/// the 16 bit color. This smooths out visible steps of slow fades at low brightness.
+pub struct Ditherer {
+    /// Accumulated rounding error of each channel of each LED
+    errors: Vec<[i32; 3]>,
+}
+
+impl Ditherer {
+    pub fn new(led_count: usize) -> Self {
+        Ditherer {
+            errors: vec![[0; 3]; led_count],
+        }
+    }
+
+    /// Reduce the colors to 8 bit and remember the rounding error for the next frame
+    pub fn dither(&mut self, leds: &[RGB16]) -> Vec<RGB8> {
+        if self.errors.len() != leds.len() {
+            self.errors = vec![[0; 3]; leds.len()];
+        }
+
+        leds.iter()
+            .zip(self.errors.iter_mut())
+            .map(|(led, errors)| RGB8 {
+                r: Self::dither_channel(led.r, &mut errors[0]),
+                g: Self::dither_channel(led.g, &mut errors[1]),
+                b: Self::dither_channel(led.b, &mut errors[2]),
+            })
+            .collect()
+    }
+
+    fn dither_channel(value: u16, error: &mut i32) -> u8 {
+        let wanted = value as i32 + *error;
+        let shown = ((wanted + STEP / 2).max(0) / STEP).min(255);
+
+        // Error that can not be shown at the ends of the range is dropped
+        *error = (wanted - shown * STEP).clamp(-STEP, STEP);
+        shown as u8
+    }
+}
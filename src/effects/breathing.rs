@@ -0,0 +1,27 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use super::Effect;
+
+/// Duration of one breath at normal speed in seconds
+const PERIOD: f32 = 4.0;
+
+/// All LEDs slowly fade in and out
+pub struct Breathing {
+    color: [f32; 3],
+    speed: f32,
+}
+
+impl Breathing {
+    pub fn new(color: [f32; 3], speed: f32) -> Self {
+        Breathing { color, speed }
+    }
+}
+
+impl Effect for Breathing {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let phase = time.as_secs_f32() * self.speed / PERIOD * 2.0 * PI;
+        let level = (1.0 - phase.cos()) / 2.0;
+        leds.fill(self.color.map(|channel| channel * level));
+    }
+}
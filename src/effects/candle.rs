@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use super::Effect;
+
+/// Updates of the flicker per second at normal speed
+const FLICKER_RATE: f32 = 15.0;
+
+/// Every LED flickers independently like a candle flame
+pub struct Candle {
+    color: [f32; 3],
+    speed: f32,
+    levels: Vec<f32>,
+    last_step: u64,
+    random: u32,
+}
+
+impl Candle {
+    pub fn new(color: [f32; 3], speed: f32) -> Self {
+        Candle {
+            color,
+            speed,
+            levels: Vec::new(),
+            last_step: 0,
+            random: 0x9e37_79b9,
+        }
+    }
+
+    /// Xorshift random number between 0 and 1
+    fn next_random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random as f32 / u32::MAX as f32
+    }
+}
+
+impl Effect for Candle {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        if self.levels.len() != leds.len() {
+            self.levels = vec![0.8; leds.len()];
+        }
+
+        // Each step moves the brightness of every LED randomly towards a new value
+        let step = (time.as_secs_f32() * self.speed * FLICKER_RATE) as u64;
+        for _ in self.last_step..step {
+            for index in 0..self.levels.len() {
+                let target = 0.5 + 0.5 * self.next_random();
+                self.levels[index] += (target - self.levels[index]) * 0.5;
+            }
+        }
+        self.last_step = step;
+
+        for (led, level) in leds.iter_mut().zip(self.levels.iter()) {
+            *led = self.color.map(|channel| channel * level);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use super::Effect;
+
+/// Time in seconds one color needs to fill the strip at normal speed
+const WIPE_DURATION: f32 = 3.0;
+
+/// The LEDs are filled one after another with the next color
+pub struct ColorWipe {
+    colors: Vec<[f32; 3]>,
+    speed: f32,
+}
+
+impl ColorWipe {
+    pub fn new(mut colors: Vec<[f32; 3]>, speed: f32) -> Self {
+        // A single color wipes over black
+        if colors.len() == 1 {
+            colors.push([0.0; 3]);
+        }
+        ColorWipe { colors, speed }
+    }
+}
+
+impl Effect for ColorWipe {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let wipes = time.as_secs_f32() * self.speed / WIPE_DURATION;
+        let current = wipes.floor() as usize % self.colors.len();
+        let previous = (current + self.colors.len() - 1) % self.colors.len();
+        let filled = (wipes.fract() * leds.len() as f32) as usize;
+
+        for (index, led) in leds.iter_mut().enumerate() {
+            *led = if index < filled {
+                self.colors[current]
+            } else {
+                self.colors[previous]
+            };
+        }
+    }
+}
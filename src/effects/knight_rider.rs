@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use super::Effect;
+
+/// Time in seconds the light needs from one end of the strip to the other at normal speed
+const SWEEP_DURATION: f32 = 2.0;
+
+/// Length of the tail as a fraction of the strip
+const TAIL: f32 = 0.08;
+
+/// A light that sweeps back and forth along the strip and leaves a fading tail
+pub struct KnightRider {
+    color: [f32; 3],
+    speed: f32,
+}
+
+impl KnightRider {
+    pub fn new(color: [f32; 3], speed: f32) -> Self {
+        KnightRider { color, speed }
+    }
+}
+
+impl Effect for KnightRider {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let count = leds.len() as f32;
+        let sweeps = time.as_secs_f32() * self.speed / SWEEP_DURATION;
+
+        // Triangle wave between 0 and 1
+        let phase = sweeps.rem_euclid(2.0);
+        let position = if phase < 1.0 { phase } else { 2.0 - phase } * (count - 1.0);
+        let tail = (TAIL * count).max(1.0);
+
+        for (index, led) in leds.iter_mut().enumerate() {
+            let distance = (index as f32 - position).abs();
+            let level = (1.0 - distance / tail).max(0.0);
+            *led = self.color.map(|channel| channel * level);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::settings::{Direction, Settings, StartCorner};
+
+/// Position of every LED around the screen. Coordinates are in the range 0..1 with the origin in
+/// the top left corner of the screen.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub width: f32,
+    pub height: f32,
+    pub positions: Vec<[f32; 2]>,
+}
+
+impl Layout {
+    /// Spread the LEDs evenly along the border of the screen, starting at the configured corner
+    pub fn new(settings: &Settings) -> Self {
+        let (width, height): (f64, f64) = settings.processing_resolution.into();
+        let (width, height) = (width as f32, height as f32);
+        let led_count = settings.led_count.max(0) as usize;
+
+        // Corners in clockwise order
+        let mut corners: Vec<[f32; 2]> = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let start = match settings.start_corner {
+            StartCorner::TL => 0,
+            StartCorner::TR => 1,
+            StartCorner::BR => 2,
+            StartCorner::BL => 3,
+        };
+        corners.rotate_left(start);
+        if let Direction::CCW = settings.direction {
+            corners[1..].reverse();
+        }
+
+        // Length of each edge in pixels, starting with the edge after the first corner
+        let edges: Vec<f32> = (0..4)
+            .map(|edge| {
+                let [x0, y0] = corners[edge];
+                let [x1, y1] = corners[(edge + 1) % 4];
+                (x1 - x0).abs() * width + (y1 - y0).abs() * height
+            })
+            .collect();
+        let perimeter = 2.0 * (width + height);
+
+        let positions = (0..led_count)
+            .map(|index| {
+                // Each LED sits in the middle of its part of the border
+                let mut distance = (index as f32 + 0.5) / led_count as f32 * perimeter;
+                let mut edge = 0;
+                while edge < 3 && distance > edges[edge] {
+                    distance -= edges[edge];
+                    edge += 1;
+                }
+
+                let t = distance / edges[edge];
+                let [x0, y0] = corners[edge];
+                let [x1, y1] = corners[(edge + 1) % 4];
+                [x0 + (x1 - x0) * t, y0 + (y1 - y0) * t]
+            })
+            .collect();
+
+        Layout {
+            width,
+            height,
+            positions,
+        }
+    }
+}
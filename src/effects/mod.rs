@@ -0,0 +1,118 @@
+mod breathing;
+mod candle;
+mod color_wipe;
+mod knight_rider;
+mod layout;
+mod names;
+mod rainbow;
+mod script;
+mod static_color;
+mod test_pattern;
+
+use std::time::Duration;
+
+use smart_leds::RGB16;
+use tracing::warn;
+
+use crate::color;
+use crate::settings::{EffectKind, EffectSettings};
+
+use breathing::Breathing;
+use candle::Candle;
+use color_wipe::ColorWipe;
+use knight_rider::KnightRider;
+pub use layout::Layout;
+pub use names::{by_name, names, Summary};
+use rainbow::Rainbow;
+pub use script::available_scripts;
+use script::Script;
+use static_color::StaticColor;
+use test_pattern::TestPattern;
+
+/// Priority of the captured colors. Effects with a lower priority are only shown while the
+/// capture is not active, effects with a higher priority override the capture.
+pub const CAPTURE_PRIORITY: u8 = 100;
+
+/// Priority of the idle effect from the settings
+pub const IDLE_PRIORITY: u8 = 50;
+
+/// Priority of effects that have been started remotely
+pub const MANUAL_PRIORITY: u8 = 200;
+
+/// Color used by effects if no colors have been configured
+pub const DEFAULT_COLOR: [u8; 3] = [255, 147, 41];
+
+/// Produces LED frames without a video source
+pub trait Effect: Send {
+    /// Render the effect at the given time since it has been started. Colors are in the range
+    /// 0..1 and will be scaled by the brightness of the effect.
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]);
+}
+
+/// Create an effect from its settings
+pub fn create(settings: &EffectSettings, layout: &Layout) -> Box<dyn Effect> {
+    let colors: Vec<[f32; 3]> = if settings.colors.is_empty() {
+        vec![to_unit(DEFAULT_COLOR)]
+    } else {
+        settings
+            .colors
+            .iter()
+            .map(|color| to_unit(*color))
+            .collect()
+    };
+    let speed = settings.speed;
+
+    match settings.effect {
+        EffectKind::StaticColor => Box::new(StaticColor::new(colors[0])),
+        EffectKind::Breathing => Box::new(Breathing::new(colors[0], speed)),
+        EffectKind::Rainbow => Box::new(Rainbow::new(speed)),
+        EffectKind::ColorWipe => Box::new(ColorWipe::new(colors, speed)),
+        EffectKind::Candle => Box::new(Candle::new(colors[0], speed)),
+        EffectKind::KnightRider => Box::new(KnightRider::new(colors[0], speed)),
+        EffectKind::TestPattern => Box::new(TestPattern::new(layout, speed)),
+        EffectKind::Script => match settings.script_path() {
+            Ok(path) => Box::new(Script::new(path, layout)),
+            Err(e) => {
+                // LEDs stay black like for a script that can not be loaded
+                warn!("{:#}", e);
+                Box::new(StaticColor::new([0.0; 3]))
+            }
+        },
+    }
+}
+
+/// An effect that is currently running in the output
+pub struct RunningEffect {
+    pub settings: EffectSettings,
+    effect: Box<dyn Effect>,
+    time: Duration,
+    buffer: Vec<[f32; 3]>,
+}
+
+impl RunningEffect {
+    pub fn new(settings: EffectSettings, layout: &Layout) -> Self {
+        RunningEffect {
+            effect: create(&settings, layout),
+            settings,
+            time: Duration::ZERO,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Advance the effect by elapsed and render the next frame
+    pub fn render(&mut self, elapsed: Duration, led_count: usize) -> Vec<RGB16> {
+        self.time += elapsed;
+        self.buffer.resize(led_count, [0.0; 3]);
+        self.effect.render(self.time, &mut self.buffer);
+
+        let brightness = self.settings.brightness.clamp(0.0, 1.0);
+        self.buffer
+            .iter()
+            .map(|led| color::from_unit(led.map(|channel| channel * brightness)))
+            .collect()
+    }
+}
+
+fn to_unit(color: [u8; 3]) -> [f32; 3] {
+    color.map(|channel| channel as f32 / 255.0)
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+
+use super::{available_scripts, DEFAULT_COLOR};
+use crate::control::State;
+use crate::settings::{EffectKind, EffectSettings};
+
+/// Name that stops the current effect and shows the captured colors again
+const AMBILIGHT: &str = "Ambilight";
+
+/// Effects that can be chosen by name besides the scripts
+const NAMED: [EffectKind; 7] = [
+    EffectKind::StaticColor,
+    EffectKind::Breathing,
+    EffectKind::Rainbow,
+    EffectKind::ColorWipe,
+    EffectKind::Candle,
+    EffectKind::KnightRider,
+    EffectKind::TestPattern,
+];
+
+/// Names of everything that can be chosen: the ambilight, the built-in effects and the scripts
+pub fn names() -> Vec<String> {
+    let mut names = vec![AMBILIGHT.to_string()];
+    names.extend(NAMED.iter().map(kind_name));
+    names.extend(available_scripts());
+    names
+}
+
+/// The effect with the given name or None for the ambilight. Built-in effects are matched
+/// regardless of case, spaces, '-' and '_' so "knight-rider" chooses KnightRider. Scripts have
+/// to be named exactly.
+pub fn by_name(name: &str) -> Result<Option<EffectSettings>> {
+    let simplified = simplify(name);
+    if simplified == simplify(AMBILIGHT) {
+        return Ok(None);
+    }
+
+    if let Some(kind) = NAMED
+        .iter()
+        .find(|kind| simplify(&kind_name(kind)) == simplified)
+    {
+        return Ok(Some(EffectSettings::new(*kind)));
+    }
+    if available_scripts().iter().any(|script| script == name) {
+        let mut effect = EffectSettings::new(EffectKind::Script);
+        effect.script = Some(name.to_string());
+        return Ok(Some(effect));
+    }
+    Err(anyhow!("Unknown effect {}", name))
+}
+
+/// Name of an effect as accepted by by_name
+pub fn name(effect: Option<&EffectSettings>) -> String {
+    match effect {
+        Some(effect) => match (&effect.effect, &effect.script) {
+            (EffectKind::Script, Some(script)) => script.clone(),
+            (kind, _) => kind_name(kind),
+        },
+        None => AMBILIGHT.to_string(),
+    }
+}
+
+/// What the LEDs show, as reported by the remote interfaces
+pub struct Summary {
+    pub enabled: bool,
+    /// Brightness between 0 and 1
+    pub brightness: f32,
+    /// Name of the effect, see name
+    pub effect: String,
+    /// First color of the effect. Effects without colors and the ambilight report the default
+    /// color.
+    pub color: [u8; 3],
+}
+
+impl Summary {
+    pub fn new(state: &State) -> Self {
+        let effect = state.effect.as_ref();
+        Summary {
+            enabled: state.enabled,
+            brightness: state.brightness.clamp(0.0, 1.0),
+            effect: name(effect),
+            color: effect
+                .and_then(|effect| effect.colors.first().copied())
+                .unwrap_or(DEFAULT_COLOR),
+        }
+    }
+}
+
+/// Effects are named like in the settings
+fn kind_name(kind: &EffectKind) -> String {
+    format!("{:?}", kind)
+}
+
+fn simplify(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use super::Effect;
+use crate::color;
+
+/// Duration of one rotation at normal speed in seconds
+const PERIOD: f32 = 10.0;
+
+/// A full rainbow spread over the strip that rotates around the screen
+pub struct Rainbow {
+    speed: f32,
+}
+
+impl Rainbow {
+    pub fn new(speed: f32) -> Self {
+        Rainbow { speed }
+    }
+}
+
+impl Effect for Rainbow {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let offset = time.as_secs_f32() * self.speed / PERIOD;
+        let count = leds.len() as f32;
+
+        for (index, led) in leds.iter_mut().enumerate() {
+            *led = color::from_hsv(index as f32 / count + offset, 1.0, 1.0);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+use tracing::{info, warn};
+
+use super::{Effect, Layout};
+use crate::color;
+use crate::settings::{Settings, SCRIPT_EXTENSION};
+
+/// How often the script file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Operations a script may use per frame so a broken script can not block the output
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Names of the scripts in the effects directory, sorted alphabetically
+pub fn available_scripts() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(Settings::effects_dir()) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == SCRIPT_EXTENSION)
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Effect written in Rhai. The script has to define a function `render(time, layout)` that
+/// returns an array with one `[r, g, b]` color per LED. Channels are floats between 0 and 1.
+///
+/// `time` is the time in seconds since the effect has been started. `layout` is a map with the
+/// `count` of LEDs, the `width` and `height` of the screen and the `leds` as array of maps with the
+/// position `x` and `y` of each LED between 0 and 1. The function `hsv(hue, saturation, value)`
+/// returns a color.
+///
+/// Scripts are read from the effects directory next to the settings file and reloaded as soon as
+/// they change.
+pub struct Script {
+    name: String,
+    path: PathBuf,
+    engine: Engine,
+    ast: Option<AST>,
+    modified: Option<SystemTime>,
+    /// Effect time of the last check for changes
+    last_check: Option<Duration>,
+    layout: Dynamic,
+    /// Whether the last frame failed. Only used to log each error once.
+    failed: bool,
+}
+
+impl Script {
+    pub fn new(path: PathBuf, layout: &Layout) -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("hsv", |hue: FLOAT, saturation: FLOAT, value: FLOAT| {
+            let rgb = color::from_hsv(hue as f32, saturation as f32, value as f32);
+            rgb.iter()
+                .map(|channel| Dynamic::from(*channel as FLOAT))
+                .collect::<Array>()
+        });
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Script {
+            name,
+            path,
+            engine,
+            ast: None,
+            modified: None,
+            last_check: None,
+            layout: Self::layout_map(layout),
+            failed: false,
+        }
+    }
+
+    /// Compile the script again if the file has changed. If the new version can not be compiled
+    /// the previous one keeps running.
+    fn reload(&mut self) {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified());
+        let modified = match modified {
+            Ok(modified) => modified,
+            Err(e) => {
+                if self.modified.is_some() || self.last_check.is_none() {
+                    warn!("Could not read effect script {:?}: {}", self.path, e);
+                }
+                self.modified = None;
+                return;
+            }
+        };
+        if self.modified == Some(modified) {
+            return;
+        }
+        self.modified = Some(modified);
+
+        let compiled = fs::read_to_string(&self.path)
+            .map_err(|e| anyhow!(e))
+            .and_then(|source| self.engine.compile(source).map_err(|e| anyhow!(e)));
+        match compiled {
+            Ok(ast) => {
+                info!("Loaded effect script {:?}", self.path);
+                self.ast = Some(ast);
+                self.failed = false;
+            }
+            Err(e) => warn!("Could not compile effect script {:?}: {}", self.path, e),
+        }
+    }
+
+    fn run(&self, ast: &AST, time: Duration, leds: &mut [[f32; 3]]) -> Result<()> {
+        let colors: Array = self
+            .engine
+            .call_fn(
+                &mut Scope::new(),
+                ast,
+                "render",
+                (time.as_secs_f64() as FLOAT, self.layout.clone()),
+            )
+            .map_err(|e| anyhow!("{}", e))?;
+
+        // LEDs without a color stay black
+        leds.fill([0.0; 3]);
+        for (led, color) in leds.iter_mut().zip(colors) {
+            let channels = color
+                .try_cast::<Array>()
+                .filter(|channels| channels.len() == 3)
+                .ok_or_else(|| anyhow!("Colors must be arrays of three numbers"))?;
+
+            for (value, channel) in led.iter_mut().zip(channels) {
+                let channel = channel
+                    .as_float()
+                    .or_else(|_| channel.as_int().map(|int| int as FLOAT))
+                    .map_err(|_| anyhow!("Color channels must be numbers"))?;
+                *value = (channel as f32).clamp(0.0, 1.0);
+            }
+        }
+        Ok(())
+    }
+
+    fn layout_map(layout: &Layout) -> Dynamic {
+        let leds: Array = layout
+            .positions
+            .iter()
+            .map(|[x, y]| {
+                let mut position = Map::new();
+                position.insert("x".into(), Dynamic::from(*x as FLOAT));
+                position.insert("y".into(), Dynamic::from(*y as FLOAT));
+                Dynamic::from_map(position)
+            })
+            .collect();
+
+        let mut map = Map::new();
+        map.insert("count".into(), Dynamic::from(leds.len() as INT));
+        map.insert("width".into(), Dynamic::from(layout.width as FLOAT));
+        map.insert("height".into(), Dynamic::from(layout.height as FLOAT));
+        map.insert("leds".into(), Dynamic::from_array(leds));
+        Dynamic::from_map(map)
+    }
+}
+
+impl Effect for Script {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let check = match self.last_check {
+            Some(last) => time.saturating_sub(last) >= RELOAD_INTERVAL,
+            None => true,
+        };
+        if check {
+            self.reload();
+            self.last_check = Some(time);
+        }
+
+        let result = match &self.ast {
+            Some(ast) => self.run(ast, time, leds),
+            None => Err(anyhow!("Script has not been loaded")),
+        };
+
+        if let Err(e) = result {
+            if !self.failed {
+                warn!("Effect script {} failed: {}", self.name, e);
+                self.failed = true;
+            }
+            leds.fill([0.0; 3]);
+        }
+    }
+}
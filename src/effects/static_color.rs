@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+use super::Effect;
+
+/// All LEDs show the same color
+pub struct StaticColor {
+    color: [f32; 3],
+}
+
+impl StaticColor {
+    pub fn new(color: [f32; 3]) -> Self {
+        StaticColor { color }
+    }
+}
+
+impl Effect for StaticColor {
+    fn render(&mut self, _time: Duration, leds: &mut [[f32; 3]]) {
+        leds.fill(self.color);
+    }
+}
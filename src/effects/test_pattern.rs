@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use super::{Effect, Layout};
+
+/// Colors of the top, right, bottom and left edge
+const EDGE_COLORS: [[f32; 3]; 4] = [
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0],
+];
+
+/// Brightness of the edge colors so the first LED and the runner stand out
+const EDGE_LEVEL: f32 = 0.25;
+
+/// LEDs the runner moves per second at normal speed
+const RUNNER_SPEED: f32 = 15.0;
+
+/// Every edge of the screen gets its own color (top red, right green, bottom blue, left yellow).
+/// The first LED is white and a white light runs along the strip in its direction. Wrong layout
+/// settings are easy to spot this way.
+pub struct TestPattern {
+    /// Edge of every LED as index into EDGE_COLORS
+    edges: Vec<usize>,
+    speed: f32,
+}
+
+impl TestPattern {
+    pub fn new(layout: &Layout, speed: f32) -> Self {
+        let edges = layout
+            .positions
+            .iter()
+            .map(|&[x, y]| {
+                // The edge the LED is closest to
+                let distances = [y, 1.0 - x, 1.0 - y, x];
+                (0..4)
+                    .min_by(|a, b| distances[*a].total_cmp(&distances[*b]))
+                    .unwrap_or(0)
+            })
+            .collect();
+        TestPattern { edges, speed }
+    }
+}
+
+impl Effect for TestPattern {
+    fn render(&mut self, time: Duration, leds: &mut [[f32; 3]]) {
+        let count = leds.len();
+        if count == 0 {
+            return;
+        }
+        let runner = (time.as_secs_f32() * self.speed * RUNNER_SPEED) as usize % count;
+
+        for (index, led) in leds.iter_mut().enumerate() {
+            let edge = self.edges.get(index).copied().unwrap_or(0);
+            *led = if index == 0 || index == runner {
+                [1.0; 3]
+            } else {
+                EDGE_COLORS[edge].map(|channel| channel * EDGE_LEVEL)
+            };
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::color;
+use crate::control::{Command, Control, State};
+use crate::effects::Summary;
+use crate::settings::{EffectKind, EffectSettings, HomeKitSettings};
+
+/// Id of the only accessory
+pub const AID: u64 = 1;
+
+// Instance ids of the services and characteristics
+const INFORMATION: u64 = 1;
+const IDENTIFY: u64 = 2;
+const MANUFACTURER: u64 = 3;
+const MODEL: u64 = 4;
+const NAME: u64 = 5;
+const SERIAL_NUMBER: u64 = 6;
+const FIRMWARE_REVISION: u64 = 7;
+const PROTOCOL: u64 = 8;
+const VERSION: u64 = 9;
+const LIGHTBULB: u64 = 10;
+const ON: u64 = 11;
+const BRIGHTNESS: u64 = 12;
+const HUE: u64 = 13;
+const SATURATION: u64 = 14;
+const LIGHTBULB_NAME: u64 = 15;
+const AMBILIGHT_SWITCH: u64 = 16;
+const AMBILIGHT: u64 = 17;
+const AMBILIGHT_NAME: u64 = 18;
+
+/// Characteristics whose value can change and can be observed by controllers
+pub const OBSERVABLE: [u64; 5] = [ON, BRIGHTNESS, HUE, SATURATION, AMBILIGHT];
+
+// Status codes of characteristic requests
+pub const STATUS_READ_ONLY: i64 = -70404;
+pub const STATUS_WRITE_ONLY: i64 = -70405;
+pub const STATUS_NOT_FOUND: i64 = -70409;
+pub const STATUS_INVALID_VALUE: i64 = -70410;
+
+/// Lightbulb for the color and brightness and a switch that turns the ambilight on. The
+/// lightbulb shows a static color while the switch is off.
+pub fn database(settings: &HomeKitSettings, state: &State) -> Value {
+    let value = |iid| read(iid, settings, state).unwrap_or(Value::Null);
+    let string = |iid, kind: &str| {
+        json!({
+            "iid": iid, "type": kind, "perms": ["pr"], "format": "string", "value": value(iid),
+        })
+    };
+
+    json!({
+        "accessories": [{
+            "aid": AID,
+            "services": [
+                {
+                    "iid": INFORMATION,
+                    "type": "3E",
+                    "characteristics": [
+                        { "iid": IDENTIFY, "type": "14", "perms": ["pw"], "format": "bool" },
+                        string(MANUFACTURER, "20"),
+                        string(MODEL, "21"),
+                        string(NAME, "23"),
+                        string(SERIAL_NUMBER, "30"),
+                        string(FIRMWARE_REVISION, "52"),
+                    ],
+                },
+                {
+                    "iid": PROTOCOL,
+                    "type": "A2",
+                    "characteristics": [string(VERSION, "37")],
+                },
+                {
+                    "iid": LIGHTBULB,
+                    "type": "43",
+                    "primary": true,
+                    "characteristics": [
+                        {
+                            "iid": ON, "type": "25", "perms": ["pr", "pw", "ev"],
+                            "format": "bool", "value": value(ON),
+                        },
+                        {
+                            "iid": BRIGHTNESS, "type": "8", "perms": ["pr", "pw", "ev"],
+                            "format": "int", "unit": "percentage", "minValue": 0,
+                            "maxValue": 100, "minStep": 1, "value": value(BRIGHTNESS),
+                        },
+                        {
+                            "iid": HUE, "type": "13", "perms": ["pr", "pw", "ev"],
+                            "format": "float", "unit": "arcdegrees", "minValue": 0,
+                            "maxValue": 360, "minStep": 1, "value": value(HUE),
+                        },
+                        {
+                            "iid": SATURATION, "type": "2F", "perms": ["pr", "pw", "ev"],
+                            "format": "float", "unit": "percentage", "minValue": 0,
+                            "maxValue": 100, "minStep": 1, "value": value(SATURATION),
+                        },
+                        string(LIGHTBULB_NAME, "23"),
+                    ],
+                },
+                {
+                    "iid": AMBILIGHT_SWITCH,
+                    "type": "49",
+                    "characteristics": [
+                        {
+                            "iid": AMBILIGHT, "type": "25", "perms": ["pr", "pw", "ev"],
+                            "format": "bool", "value": value(AMBILIGHT),
+                        },
+                        string(AMBILIGHT_NAME, "23"),
+                    ],
+                },
+            ],
+        }],
+    })
+}
+
+/// Color of the current effect as hue in degrees and saturation in percent
+fn hue_saturation(summary: &Summary) -> (f32, f32) {
+    let [r, g, b] = summary.color;
+    let (hue, saturation, _) =
+        color::to_hsv([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]);
+    ((hue * 360.0).round(), (saturation * 100.0).round())
+}
+
+/// Current value of a readable characteristic
+pub fn read(iid: u64, settings: &HomeKitSettings, state: &State) -> Result<Value, i64> {
+    let summary = Summary::new(state);
+    let (hue, saturation) = hue_saturation(&summary);
+    match iid {
+        MANUFACTURER => Ok(json!("Rustylight")),
+        MODEL => Ok(json!("Ambilight")),
+        NAME | LIGHTBULB_NAME => Ok(json!(settings.name)),
+        SERIAL_NUMBER => Ok(json!("1")),
+        FIRMWARE_REVISION => Ok(json!(env!("CARGO_PKG_VERSION"))),
+        VERSION => Ok(json!("1.1.0")),
+        AMBILIGHT_NAME => Ok(json!("Ambilight")),
+        ON => Ok(json!(summary.enabled)),
+        BRIGHTNESS => Ok(json!((summary.brightness * 100.0).round() as i64)),
+        HUE => Ok(json!(hue)),
+        SATURATION => Ok(json!(saturation)),
+        AMBILIGHT => Ok(json!(state.effect.is_none())),
+        IDENTIFY => Err(STATUS_WRITE_ONLY),
+        _ => Err(STATUS_NOT_FOUND),
+    }
+}
+
+/// Values written in one request. Hue and saturation usually arrive together and are combined to
+/// one color.
+#[derive(Default)]
+pub struct Changes {
+    enabled: Option<bool>,
+    brightness: Option<f32>,
+    hue: Option<f32>,
+    saturation: Option<f32>,
+    ambilight: Option<bool>,
+}
+
+impl Changes {
+    /// Remember the value of a writable characteristic
+    pub fn set(&mut self, iid: u64, value: &Value) -> Result<(), i64> {
+        let number = || value.as_f64().ok_or(STATUS_INVALID_VALUE);
+        match iid {
+            ON => self.enabled = Some(as_bool(value)?),
+            BRIGHTNESS => self.brightness = Some((number()? / 100.0).clamp(0.0, 1.0) as f32),
+            HUE => self.hue = Some(number()?.clamp(0.0, 360.0) as f32),
+            SATURATION => self.saturation = Some(number()?.clamp(0.0, 100.0) as f32),
+            AMBILIGHT => self.ambilight = Some(as_bool(value)?),
+            IDENTIFY => info!("HomeKit identify"),
+            NAME | MANUFACTURER | MODEL | SERIAL_NUMBER | FIRMWARE_REVISION | VERSION
+            | LIGHTBULB_NAME | AMBILIGHT_NAME => return Err(STATUS_READ_ONLY),
+            _ => return Err(STATUS_NOT_FOUND),
+        }
+        Ok(())
+    }
+
+    /// Pass the changes to the main loop
+    pub fn apply(self, control: &Control) -> Result<()> {
+        let state = control.state();
+
+        if let Some(enabled) = self.enabled {
+            control.send(Command::SetEnabled(enabled))?;
+        }
+        if let Some(brightness) = self.brightness {
+            control.send(Command::SetBrightness(brightness))?;
+        }
+
+        // A new color is shown by the current effect or on its own
+        if self.hue.is_some() || self.saturation.is_some() {
+            let (hue, saturation) = hue_saturation(&Summary::new(&state));
+            let rgb = color::from_hsv(
+                self.hue.unwrap_or(hue) / 360.0,
+                self.saturation.unwrap_or(saturation) / 100.0,
+                1.0,
+            );
+            let mut effect = state
+                .effect
+                .unwrap_or_else(|| EffectSettings::new(EffectKind::StaticColor));
+            effect.colors = vec![rgb.map(|channel| (channel * 255.0).round() as u8)];
+            control.send(Command::SetEffect(Some(effect)))?;
+        } else if self.ambilight == Some(false) && state.effect.is_none() {
+            let effect = EffectSettings::new(EffectKind::StaticColor);
+            control.send(Command::SetEffect(Some(effect)))?;
+        } else if self.ambilight == Some(true) {
+            control.send(Command::SetEffect(None))?;
+        }
+        Ok(())
+    }
+}
+
+fn as_bool(value: &Value) -> Result<bool, i64> {
+    // Controllers send booleans as true/false or 1/0
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::Number(number) => Ok(number.as_f64() != Some(0.0)),
+        _ => Err(STATUS_INVALID_VALUE),
+    }
+}
+
+/// Parse an id of the form "aid.iid"
+pub fn parse_id(id: &str) -> Result<(u64, u64)> {
+    let (aid, iid) = id
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Invalid characteristic id {}", id))?;
+    Ok((aid.parse()?, iid.parse()?))
+}
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha512;
+
+/// Length of the authentication tag that follows the encrypted data
+const TAG_LENGTH: usize = 16;
+
+/// Maximum amount of plain data in one frame of an encrypted session
+const MAX_FRAME_LENGTH: usize = 1024;
+
+/// Derive a key with HKDF-SHA512
+pub fn derive_key(secret: &[u8], salt: &str, info: &str) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha512>::new(Some(salt.as_bytes()), secret)
+        .expand(info.as_bytes(), &mut key)
+        .expect("32 bytes are a valid HKDF output length");
+    key
+}
+
+/// Encrypt a pairing message. The nonce is a label like "PS-Msg05".
+pub fn seal(key: &[u8; 32], label: &[u8; 8], data: &[u8]) -> Vec<u8> {
+    encrypt(key, &nonce(*label), data, &[])
+}
+
+/// Decrypt a pairing message
+pub fn open(key: &[u8; 32], label: &[u8; 8], data: &[u8]) -> Result<Vec<u8>> {
+    decrypt(key, &nonce(*label), data, &[])
+}
+
+fn nonce(counter: [u8; 8]) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter);
+    nonce
+}
+
+fn encrypt(key: &[u8; 32], nonce: &[u8; 12], data: &[u8], aad: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+        .expect("Encryption does not fail for valid keys")
+}
+
+fn decrypt(key: &[u8; 32], nonce: &[u8; 12], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+        .map_err(|_| anyhow!("Could not decrypt message"))
+}
+
+/// Encryption of a connection after pair verify. Data is sent in frames with the length as
+/// additional authenticated data and a counter as nonce.
+pub struct Session {
+    read_key: [u8; 32],
+    write_key: [u8; 32],
+    read_counter: u64,
+    write_counter: u64,
+}
+
+impl Session {
+    pub fn new(shared_secret: &[u8]) -> Self {
+        Session {
+            read_key: derive_key(
+                shared_secret,
+                "Control-Salt",
+                "Control-Write-Encryption-Key",
+            ),
+            write_key: derive_key(shared_secret, "Control-Salt", "Control-Read-Encryption-Key"),
+            read_counter: 0,
+            write_counter: 0,
+        }
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        for frame in data.chunks(MAX_FRAME_LENGTH) {
+            let length = (frame.len() as u16).to_le_bytes();
+            let nonce = nonce(self.write_counter.to_le_bytes());
+            self.write_counter += 1;
+
+            encrypted.extend_from_slice(&length);
+            encrypted.extend_from_slice(&encrypt(&self.write_key, &nonce, frame, &length));
+        }
+        encrypted
+    }
+
+    /// Decrypt all complete frames and remove them from the buffer
+    pub fn decrypt(&mut self, buffer: &mut Vec<u8>) -> Result<Vec<u8>> {
+        let mut decrypted = Vec::new();
+        while let Some(length) = buffer.get(..2) {
+            let length = u16::from_le_bytes([length[0], length[1]]) as usize;
+            if length > MAX_FRAME_LENGTH {
+                return Err(anyhow!("Frame is too long"));
+            }
+            let end = 2 + length + TAG_LENGTH;
+            if buffer.len() < end {
+                break;
+            }
+
+            let nonce = nonce(self.read_counter.to_le_bytes());
+            self.read_counter += 1;
+            let frame = decrypt(&self.read_key, &nonce, &buffer[2..end], &buffer[..2])?;
+            decrypted.extend_from_slice(&frame);
+            buffer.drain(..end);
+        }
+        Ok(decrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The accessory and the controller use the keys the other way around
+    fn sessions() -> (Session, Session) {
+        let accessory = Session::new(b"shared secret");
+        let mut controller = Session::new(b"shared secret");
+        std::mem::swap(&mut controller.read_key, &mut controller.write_key);
+        (accessory, controller)
+    }
+
+    #[test]
+    fn long_messages_are_sent_in_several_frames() {
+        let (mut accessory, mut controller) = sessions();
+        let message: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+
+        let mut encrypted = accessory.encrypt(&message);
+        assert_eq!(encrypted.len(), 3000 + 3 * (2 + TAG_LENGTH));
+        assert_eq!(encrypted[..2], 1024u16.to_le_bytes());
+
+        assert_eq!(controller.decrypt(&mut encrypted).unwrap(), message);
+        assert!(encrypted.is_empty());
+        assert_eq!(accessory.write_counter, 3);
+        assert_eq!(controller.read_counter, 3);
+    }
+
+    #[test]
+    fn counters_continue_across_messages_in_both_directions() {
+        let (mut accessory, mut controller) = sessions();
+
+        for i in 0..5u8 {
+            let request = vec![i; 100 + i as usize];
+            let mut encrypted = controller.encrypt(&request);
+            assert_eq!(accessory.decrypt(&mut encrypted).unwrap(), request);
+
+            let response = vec![i; 2000];
+            let mut encrypted = accessory.encrypt(&response);
+            assert_eq!(controller.decrypt(&mut encrypted).unwrap(), response);
+        }
+        assert_eq!(accessory.read_counter, 5);
+        assert_eq!(accessory.write_counter, 10);
+    }
+
+    #[test]
+    fn incomplete_frames_stay_in_the_buffer() {
+        let (mut accessory, mut controller) = sessions();
+        let message = vec![42; 1500];
+        let encrypted = accessory.encrypt(&message);
+
+        let mut buffer = Vec::new();
+        let mut decrypted = Vec::new();
+        for byte in encrypted {
+            buffer.push(byte);
+            decrypted.extend(controller.decrypt(&mut buffer).unwrap());
+        }
+        assert_eq!(decrypted, message);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn replayed_frames_are_rejected() {
+        let (mut accessory, mut controller) = sessions();
+        let encrypted = accessory.encrypt(b"on");
+
+        assert_eq!(controller.decrypt(&mut encrypted.clone()).unwrap(), b"on");
+        assert!(controller.decrypt(&mut encrypted.clone()).is_err());
+    }
+
+    #[test]
+    fn frames_longer_than_the_maximum_are_rejected() {
+        let (_, mut controller) = sessions();
+        let mut buffer = 1025u16.to_le_bytes().to_vec();
+        assert!(controller.decrypt(&mut buffer).is_err());
+    }
+}
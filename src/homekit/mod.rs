@@ -0,0 +1,605 @@
+mod accessory;
+mod crypto;
+mod pairing;
+mod srp;
+mod storage;
+mod tlv;
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::control::{Control, State};
+use crate::settings::HomeKitSettings;
+use accessory::{Changes, AID, OBSERVABLE};
+use crypto::Session;
+use pairing::Pairings;
+use storage::Storage;
+
+/// Version of the accessory database. It has to be increased whenever the services change.
+const CONFIG_NUMBER: u32 = 1;
+
+/// Category that is shown while adding the accessory
+const CATEGORY_LIGHTBULB: u32 = 5;
+
+const SERVICE_TYPE: &str = "_hap._tcp.local.";
+
+/// How often each connection checks for changes it has to notify the controller about
+const EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Requests of controllers are small. Connections that send more are closed instead of buffering
+/// everything they send.
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+// Status codes of failed requests
+const STATUS_INSUFFICIENT_PRIVILEGES: i64 = -70401;
+const STATUS_COMMUNICATION_FAILURE: i64 = -70402;
+const STATUS_NOTIFICATION_NOT_SUPPORTED: i64 = -70406;
+
+/// Everything the connections share
+struct Server {
+    settings: HomeKitSettings,
+    control: Control,
+    storage: Mutex<Storage>,
+    mdns: ServiceDaemon,
+    port: u16,
+}
+
+/// Start the HomeKit accessory. It is advertised with mDNS and can be added in the Home app with
+/// the setup code from the settings.
+pub fn spawn(settings: &HomeKitSettings, control: Control) -> Result<()> {
+    validate_setup_code(&settings.setup_code)?;
+    let storage = Storage::load()?;
+    let paired = storage.is_paired();
+    let listener = TcpListener::bind(("0.0.0.0", settings.port))?;
+
+    let server = Arc::new(Server {
+        settings: settings.clone(),
+        control,
+        storage: Mutex::new(storage),
+        mdns: ServiceDaemon::new()?,
+        port: listener.local_addr()?.port(),
+    });
+    server.advertise()?;
+    info!("HomeKit accessory listening on port {}", server.port);
+    if !paired {
+        // The setup code is a secret, so it is only shown to those who ask for debug output
+        info!("HomeKit accessory is waiting to be paired with the setup code from the settings");
+        debug!("HomeKit setup code: {}", settings.setup_code);
+    }
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    thread::spawn(move || {
+                        let peer = stream.peer_addr().ok();
+                        debug!("HomeKit connection from {:?}", peer);
+                        if let Err(e) = Connection::new(stream, server).run() {
+                            debug!("HomeKit connection from {:?} closed: {:#}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Could not accept HomeKit connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The setup code has the form XXX-XX-XXX. Trivial codes are not allowed by HomeKit.
+pub fn validate_setup_code(code: &str) -> Result<()> {
+    let digits: String = code.chars().filter(|c| *c != '-').collect();
+    let valid_format = code.len() == 10
+        && code.chars().enumerate().all(|(index, c)| match index {
+            3 | 6 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    let trivial = digits.chars().all(|c| digits.starts_with(c))
+        || digits == "12345678"
+        || digits == "87654321";
+
+    if !valid_format || trivial {
+        return Err(anyhow!(
+            "Invalid HomeKit setup code {}. It has to look like 123-45-678 and must not be trivial",
+            code
+        ));
+    }
+    Ok(())
+}
+
+impl Server {
+    fn storage(&self) -> MutexGuard<'_, Storage> {
+        self.storage.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Announce the accessory. Controllers only offer to add it while it is not paired.
+    fn advertise(&self) -> Result<()> {
+        let storage = self.storage();
+        let config_number = CONFIG_NUMBER.to_string();
+        let category = CATEGORY_LIGHTBULB.to_string();
+        let properties = [
+            ("c#", config_number.as_str()),
+            ("ff", "0"),
+            ("id", storage.device_id.as_str()),
+            ("md", self.settings.name.as_str()),
+            ("pv", "1.1"),
+            ("s#", "1"),
+            ("sf", if storage.is_paired() { "0" } else { "1" }),
+            ("ci", category.as_str()),
+        ];
+        let host = format!("{}.local.", storage.device_id.replace(':', ""));
+
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &self.settings.name,
+            &host,
+            (),
+            self.port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+        self.mdns.register(service)?;
+        Ok(())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Take the next complete request from the buffer
+    fn parse(buffer: &mut Vec<u8>) -> Result<Option<Request>> {
+        let Some(head_length) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+            if buffer.len() > MAX_HEAD_LENGTH {
+                return Err(anyhow!("Request head is too long"));
+            }
+            return Ok(None);
+        };
+        if head_length > MAX_HEAD_LENGTH {
+            return Err(anyhow!("Request head is too long"));
+        }
+        let head = std::str::from_utf8(&buffer[..head_length])?;
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let method = request_line.next().unwrap_or_default().to_string();
+        let target = request_line.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let content_length = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .map(|(_, value)| value.trim().parse::<usize>())
+            .transpose()?
+            .unwrap_or(0);
+        if content_length > MAX_BODY_LENGTH {
+            return Err(anyhow!("Request body is too long"));
+        }
+
+        let end = head_length + 4 + content_length;
+        if buffer.len() < end {
+            return Ok(None);
+        }
+        let request = Request {
+            method,
+            path: path.to_string(),
+            query: query.to_string(),
+            body: buffer[head_length + 4..end].to_vec(),
+        };
+        buffer.drain(..end);
+        Ok(Some(request))
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: Value) -> Self {
+        Response {
+            status,
+            content_type: "application/hap+json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn tlv(body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type: "application/pairing+tlv8",
+            body,
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Response {
+            status,
+            content_type: "application/hap+json",
+            body: Vec::new(),
+        }
+    }
+
+    fn to_bytes(&self, protocol: &str) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            207 => "Multi-Status",
+            400 => "Bad Request",
+            404 => "Not Found",
+            470 => "Connection Authorization Required",
+            _ => "Internal Server Error",
+        };
+        let mut bytes = format!(
+            "{} {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            protocol,
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+#[derive(Deserialize)]
+struct WriteRequest {
+    characteristics: Vec<CharacteristicWrite>,
+}
+
+#[derive(Deserialize)]
+struct CharacteristicWrite {
+    aid: u64,
+    iid: u64,
+    value: Option<Value>,
+    ev: Option<bool>,
+}
+
+/// Connection to a controller. It is encrypted once the controller has been verified.
+struct Connection {
+    stream: TcpStream,
+    server: Arc<Server>,
+    pairings: Pairings,
+    session: Option<Session>,
+    /// Session that is used after the response to pair verify has been sent
+    verified: Option<(Session, String)>,
+    /// Id of the verified controller
+    controller: Option<String>,
+    /// Encrypted data that does not form a complete frame yet
+    received: Vec<u8>,
+    /// Plain data that does not form a complete request yet
+    requests: Vec<u8>,
+    /// Last value the controller knows of every characteristic it observes
+    observed: HashMap<u64, Value>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, server: Arc<Server>) -> Self {
+        Connection {
+            stream,
+            server,
+            pairings: Pairings::default(),
+            session: None,
+            verified: None,
+            controller: None,
+            received: Vec::new(),
+            requests: Vec::new(),
+            observed: HashMap::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<()> {
+        // Reading times out regularly to notify the controller about changes
+        self.stream.set_read_timeout(Some(EVENT_INTERVAL))?;
+        let mut buffer = [0; 4096];
+
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(length) => self.receive(&buffer[..length])?,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            // Connections of removed controllers are closed
+            if let Some(controller) = &self.controller {
+                if self.server.storage().pairing(controller).is_none() {
+                    info!("HomeKit controller {} has been removed", controller);
+                    return Ok(());
+                }
+            }
+            self.send_events()?;
+        }
+    }
+
+    fn receive(&mut self, data: &[u8]) -> Result<()> {
+        match &mut self.session {
+            Some(session) => {
+                self.received.extend_from_slice(data);
+                let decrypted = session.decrypt(&mut self.received)?;
+                self.requests.extend_from_slice(&decrypted);
+            }
+            None => self.requests.extend_from_slice(data),
+        }
+
+        while let Some(request) = Request::parse(&mut self.requests)? {
+            debug!("HomeKit {} {}", request.method, request.path);
+            let response = self.handle(&request);
+            self.send(&response.to_bytes("HTTP/1.1"))?;
+
+            if let Some((session, controller)) = self.verified.take() {
+                self.session = Some(session);
+                self.controller = Some(controller);
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        match &mut self.session {
+            Some(session) => self.stream.write_all(&session.encrypt(data))?,
+            None => self.stream.write_all(data)?,
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Request) -> Response {
+        let server = Arc::clone(&self.server);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/pair-setup") => {
+                let response = self.pairings.setup(
+                    &request.body,
+                    &server.storage,
+                    &server.settings.setup_code,
+                );
+                self.readvertise();
+                Response::tlv(response)
+            }
+            ("POST", "/pair-verify") => {
+                let (response, verified) = self.pairings.verify(&request.body, &server.storage);
+                self.verified = verified;
+                Response::tlv(response)
+            }
+            ("POST", "/identify") if !server.storage().is_paired() => {
+                info!("HomeKit identify");
+                Response::empty(204)
+            }
+            _ if self.controller.is_none() => {
+                Response::json(470, json!({ "status": STATUS_INSUFFICIENT_PRIVILEGES }))
+            }
+            ("GET", "/accessories") => {
+                let state = server.control.state();
+                Response::json(200, accessory::database(&server.settings, &state))
+            }
+            ("GET", "/characteristics") => self.read_characteristics(&request.query),
+            ("PUT", "/characteristics") => self.write_characteristics(&request.body),
+            ("POST", "/pairings") => {
+                let controller = self.controller.clone().unwrap_or_default();
+                let response = pairing::manage(&request.body, &controller, &server.storage);
+                self.readvertise();
+                Response::tlv(response)
+            }
+            _ => Response::empty(404),
+        }
+    }
+
+    /// Update the advertisement after the accessory has been paired or unpaired
+    fn readvertise(&self) {
+        if let Err(e) = self.server.advertise() {
+            warn!("Could not advertise HomeKit accessory: {}", e);
+        }
+    }
+
+    fn read_characteristics(&self, query: &str) -> Response {
+        let ids = query
+            .split('&')
+            .filter_map(|parameter| parameter.strip_prefix("id="))
+            .flat_map(|ids| ids.split(','))
+            .map(accessory::parse_id)
+            .collect::<Result<Vec<_>>>();
+        let Ok(ids) = ids else {
+            return Response::empty(400);
+        };
+
+        let state = self.server.control.state();
+        let mut failed = false;
+        let characteristics: Vec<Value> = ids
+            .iter()
+            .map(|&(aid, iid)| {
+                let value = match aid {
+                    AID => accessory::read(iid, &self.server.settings, &state),
+                    _ => Err(accessory::STATUS_NOT_FOUND),
+                };
+                match value {
+                    Ok(value) => json!({ "aid": aid, "iid": iid, "value": value, "status": 0 }),
+                    Err(status) => {
+                        failed = true;
+                        json!({ "aid": aid, "iid": iid, "status": status })
+                    }
+                }
+            })
+            .collect();
+
+        // The status is only reported if any characteristic failed
+        let characteristics = if failed {
+            characteristics
+        } else {
+            characteristics
+                .into_iter()
+                .map(|mut characteristic| {
+                    if let Value::Object(fields) = &mut characteristic {
+                        fields.remove("status");
+                    }
+                    characteristic
+                })
+                .collect()
+        };
+        let status = if failed { 207 } else { 200 };
+        Response::json(status, json!({ "characteristics": characteristics }))
+    }
+
+    fn write_characteristics(&mut self, body: &[u8]) -> Response {
+        let Ok(request) = serde_json::from_slice::<WriteRequest>(body) else {
+            return Response::empty(400);
+        };
+
+        let state = self.server.control.state();
+        let mut changes = Changes::default();
+        let mut statuses = Vec::new();
+        for write in &request.characteristics {
+            let status = self.write(write, &mut changes, &state);
+            statuses.push((write.aid, write.iid, status));
+        }
+
+        if let Err(e) = changes.apply(&self.server.control) {
+            warn!("Could not apply HomeKit changes: {:#}", e);
+            for (_, _, status) in statuses.iter_mut() {
+                *status = Err(STATUS_COMMUNICATION_FAILURE);
+            }
+        }
+
+        if statuses.iter().all(|(_, _, status)| status.is_ok()) {
+            return Response::empty(204);
+        }
+        let characteristics: Vec<Value> = statuses
+            .iter()
+            .map(|(aid, iid, status)| {
+                let status = status.err().unwrap_or(0);
+                json!({ "aid": aid, "iid": iid, "status": status })
+            })
+            .collect();
+        Response::json(207, json!({ "characteristics": characteristics }))
+    }
+
+    fn write(
+        &mut self,
+        write: &CharacteristicWrite,
+        changes: &mut Changes,
+        state: &State,
+    ) -> Result<(), i64> {
+        if write.aid != AID {
+            return Err(accessory::STATUS_NOT_FOUND);
+        }
+
+        if let Some(events) = write.ev {
+            if !OBSERVABLE.contains(&write.iid) {
+                return Err(STATUS_NOTIFICATION_NOT_SUPPORTED);
+            }
+            if events {
+                let value = accessory::read(write.iid, &self.server.settings, state)?;
+                self.observed.insert(write.iid, value);
+            } else {
+                self.observed.remove(&write.iid);
+            }
+        }
+
+        if let Some(value) = &write.value {
+            changes.set(write.iid, value)?;
+            // The controller that changed a value is not notified about it
+            if let Some(observed) = self.observed.get_mut(&write.iid) {
+                *observed = value.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify the controller about changed values of the characteristics it observes
+    fn send_events(&mut self) -> Result<()> {
+        if self.observed.is_empty() {
+            return Ok(());
+        }
+
+        let state = self.server.control.state();
+        let mut changed = Vec::new();
+        for (iid, last) in self.observed.iter_mut() {
+            let Ok(value) = accessory::read(*iid, &self.server.settings, &state) else {
+                continue;
+            };
+            if !same_value(&value, last) {
+                changed.push(json!({ "aid": AID, "iid": iid, "value": value }));
+                *last = value;
+            }
+        }
+
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let event = Response::json(200, json!({ "characteristics": changed }));
+        self.send(&event.to_bytes("EVENT/1.0"))
+    }
+}
+
+/// Values are compared as numbers if possible because controllers send 1 for true or 50.0 for 50
+fn same_value(a: &Value, b: &Value) -> bool {
+    let number = |value: &Value| match value {
+        Value::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+        value => value.as_f64(),
+    };
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => (a - b).abs() < 0.5,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_parsed_once_they_are_complete() {
+        let data = b"PUT /characteristics?id=1.9 HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /accessories HTTP/1.1\r\n\r\n";
+        let mut buffer = data[..60].to_vec();
+        assert!(Request::parse(&mut buffer).unwrap().is_none());
+
+        buffer.extend_from_slice(&data[60..]);
+        let request = Request::parse(&mut buffer).unwrap().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/characteristics");
+        assert_eq!(request.query, "id=1.9");
+        assert_eq!(request.body, b"hello");
+
+        let request = Request::parse(&mut buffer).unwrap().unwrap();
+        assert_eq!(request.path, "/accessories");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn long_heads_are_rejected() {
+        let mut buffer = b"GET /".to_vec();
+        buffer.resize(MAX_HEAD_LENGTH, b'a');
+        assert!(Request::parse(&mut buffer).unwrap().is_none());
+
+        buffer.push(b'a');
+        assert!(Request::parse(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn long_bodies_are_rejected() {
+        let head = format!(
+            "POST /pairings HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LENGTH + 1
+        );
+        let mut buffer = head.into_bytes();
+        assert!(Request::parse(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, VerifyingKey};
+use rand::rngs::OsRng;
+use tracing::{info, warn};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use super::crypto::{self, Session};
+use super::srp::SrpServer;
+use super::storage::{Pairing, Storage};
+use super::tlv;
+
+/// Pair setup is refused after this many wrong setup codes until rustylight is restarted
+const MAX_ATTEMPTS: u32 = 100;
+
+// Methods of the pairings endpoint
+const ADD_PAIRING: u8 = 3;
+const REMOVE_PAIRING: u8 = 4;
+const LIST_PAIRINGS: u8 = 5;
+
+/// Permission of admin controllers
+const ADMIN: u8 = 1;
+
+/// Progress of pair setup and pair verify on one connection
+#[derive(Default)]
+pub struct Pairings {
+    srp: Option<SrpServer>,
+    /// Session key of a successful SRP exchange
+    setup_key: Option<Vec<u8>>,
+    verify: Option<Verify>,
+}
+
+struct Verify {
+    shared_secret: [u8; 32],
+    accessory_key: [u8; 32],
+    controller_key: [u8; 32],
+}
+
+/// Response that reports an error at the given state of the exchange
+fn error(state: u8, code: u8) -> Vec<u8> {
+    tlv::encode(&[(tlv::STATE, &[state]), (tlv::ERROR, &[code])])
+}
+
+fn state(items: &HashMap<u8, Vec<u8>>) -> Option<u8> {
+    items
+        .get(&tlv::STATE)
+        .and_then(|state| state.first())
+        .copied()
+}
+
+fn item(items: &HashMap<u8, Vec<u8>>, kind: u8) -> Result<&[u8]> {
+    items
+        .get(&kind)
+        .map(Vec::as_slice)
+        .ok_or_else(|| anyhow!("Missing TLV item {}", kind))
+}
+
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    let public_key: [u8; 32] = public_key
+        .try_into()
+        .map_err(|_| anyhow!("Invalid public key"))?;
+    let signature = Signature::from_slice(signature)?;
+    VerifyingKey::from_bytes(&public_key)?.verify_strict(message, &signature)?;
+    Ok(())
+}
+
+impl Pairings {
+    /// Exchange of the long term keys. The controller proves that it knows the setup code.
+    pub fn setup(&mut self, body: &[u8], storage: &Mutex<Storage>, setup_code: &str) -> Vec<u8> {
+        let items = match tlv::decode(body) {
+            Ok(items) => items,
+            Err(_) => return error(2, tlv::ERROR_UNKNOWN),
+        };
+
+        match state(&items) {
+            Some(1) => {
+                let storage = lock(storage);
+                if storage.is_paired() {
+                    info!("Refusing HomeKit pair setup because the accessory is already paired");
+                    return error(2, tlv::ERROR_UNAVAILABLE);
+                }
+                if storage.failed_attempts >= MAX_ATTEMPTS {
+                    return error(2, tlv::ERROR_MAX_TRIES);
+                }
+
+                let srp = SrpServer::new(setup_code);
+                let response = tlv::encode(&[
+                    (tlv::STATE, &[2]),
+                    (tlv::PUBLIC_KEY, &srp.public_key()),
+                    (tlv::SALT, srp.salt()),
+                ]);
+                self.srp = Some(srp);
+                self.setup_key = None;
+                response
+            }
+            Some(3) => {
+                let Some(srp) = self.srp.take() else {
+                    return error(4, tlv::ERROR_UNKNOWN);
+                };
+                let (Ok(client_key), Ok(client_proof)) =
+                    (item(&items, tlv::PUBLIC_KEY), item(&items, tlv::PROOF))
+                else {
+                    return error(4, tlv::ERROR_UNKNOWN);
+                };
+
+                match srp.verify(client_key, client_proof) {
+                    Some((key, proof)) => {
+                        self.setup_key = Some(key);
+                        tlv::encode(&[(tlv::STATE, &[4]), (tlv::PROOF, &proof)])
+                    }
+                    None => {
+                        warn!("HomeKit pair setup with a wrong setup code");
+                        lock(storage).failed_attempts += 1;
+                        error(4, tlv::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            Some(5) => {
+                let Some(key) = self.setup_key.take() else {
+                    return error(6, tlv::ERROR_UNKNOWN);
+                };
+                match Self::exchange(&key, &items, storage) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        warn!("HomeKit pair setup failed: {:#}", e);
+                        error(6, tlv::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            _ => error(2, tlv::ERROR_UNKNOWN),
+        }
+    }
+
+    /// Store the long term key of the controller and send the key of the accessory
+    fn exchange(
+        key: &[u8],
+        items: &HashMap<u8, Vec<u8>>,
+        storage: &Mutex<Storage>,
+    ) -> Result<Vec<u8>> {
+        let encryption_key =
+            crypto::derive_key(key, "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+        let data = crypto::open(
+            &encryption_key,
+            b"PS-Msg05",
+            item(items, tlv::ENCRYPTED_DATA)?,
+        )?;
+        let controller = tlv::decode(&data)?;
+        let id = item(&controller, tlv::IDENTIFIER)?;
+        let public_key = item(&controller, tlv::PUBLIC_KEY)?;
+
+        let controller_x = crypto::derive_key(
+            key,
+            "Pair-Setup-Controller-Sign-Salt",
+            "Pair-Setup-Controller-Sign-Info",
+        );
+        let info = [&controller_x[..], id, public_key].concat();
+        verify_signature(public_key, &info, item(&controller, tlv::SIGNATURE)?)?;
+
+        let mut storage = lock(storage);
+        storage.add_pairing(Pairing {
+            id: String::from_utf8(id.to_vec())?,
+            public_key: public_key.to_vec(),
+            admin: true,
+        })?;
+
+        let signing_key = storage.signing_key();
+        let accessory_key = signing_key.verifying_key().to_bytes();
+        let accessory_x = crypto::derive_key(
+            key,
+            "Pair-Setup-Accessory-Sign-Salt",
+            "Pair-Setup-Accessory-Sign-Info",
+        );
+        let info = [
+            &accessory_x[..],
+            storage.device_id.as_bytes(),
+            &accessory_key,
+        ]
+        .concat();
+        let signature = signing_key.sign(&info).to_bytes();
+
+        let accessory = tlv::encode(&[
+            (tlv::IDENTIFIER, storage.device_id.as_bytes()),
+            (tlv::PUBLIC_KEY, &accessory_key),
+            (tlv::SIGNATURE, &signature),
+        ]);
+        let encrypted = crypto::seal(&encryption_key, b"PS-Msg06", &accessory);
+        Ok(tlv::encode(&[
+            (tlv::STATE, &[6]),
+            (tlv::ENCRYPTED_DATA, &encrypted),
+        ]))
+    }
+
+    /// Start an encrypted session with a paired controller. Returns the session and the id of
+    /// the controller once both sides have been verified.
+    pub fn verify(
+        &mut self,
+        body: &[u8],
+        storage: &Mutex<Storage>,
+    ) -> (Vec<u8>, Option<(Session, String)>) {
+        let items = match tlv::decode(body) {
+            Ok(items) => items,
+            Err(_) => return (error(2, tlv::ERROR_UNKNOWN), None),
+        };
+
+        match state(&items) {
+            Some(1) => match self.start_verify(&items, storage) {
+                Ok(response) => (response, None),
+                Err(e) => {
+                    warn!("HomeKit pair verify failed: {:#}", e);
+                    (error(2, tlv::ERROR_UNKNOWN), None)
+                }
+            },
+            Some(3) => match self.finish_verify(&items, storage) {
+                Ok(verified) => (tlv::encode(&[(tlv::STATE, &[4])]), Some(verified)),
+                Err(e) => {
+                    warn!("HomeKit pair verify failed: {:#}", e);
+                    (error(4, tlv::ERROR_AUTHENTICATION), None)
+                }
+            },
+            _ => (error(2, tlv::ERROR_UNKNOWN), None),
+        }
+    }
+
+    fn start_verify(
+        &mut self,
+        items: &HashMap<u8, Vec<u8>>,
+        storage: &Mutex<Storage>,
+    ) -> Result<Vec<u8>> {
+        let controller_key: [u8; 32] = item(items, tlv::PUBLIC_KEY)?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid public key"))?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let accessory_key = PublicKey::from(&secret).to_bytes();
+        let shared_secret = secret
+            .diffie_hellman(&PublicKey::from(controller_key))
+            .to_bytes();
+
+        let storage = lock(storage);
+        let info = [
+            &accessory_key[..],
+            storage.device_id.as_bytes(),
+            &controller_key,
+        ]
+        .concat();
+        let signature = storage.signing_key().sign(&info).to_bytes();
+        let accessory = tlv::encode(&[
+            (tlv::IDENTIFIER, storage.device_id.as_bytes()),
+            (tlv::SIGNATURE, &signature),
+        ]);
+
+        let key = crypto::derive_key(
+            &shared_secret,
+            "Pair-Verify-Encrypt-Salt",
+            "Pair-Verify-Encrypt-Info",
+        );
+        let encrypted = crypto::seal(&key, b"PV-Msg02", &accessory);
+
+        self.verify = Some(Verify {
+            shared_secret,
+            accessory_key,
+            controller_key,
+        });
+        Ok(tlv::encode(&[
+            (tlv::STATE, &[2]),
+            (tlv::PUBLIC_KEY, &accessory_key),
+            (tlv::ENCRYPTED_DATA, &encrypted),
+        ]))
+    }
+
+    fn finish_verify(
+        &mut self,
+        items: &HashMap<u8, Vec<u8>>,
+        storage: &Mutex<Storage>,
+    ) -> Result<(Session, String)> {
+        let verify = self
+            .verify
+            .take()
+            .ok_or_else(|| anyhow!("Pair verify has not been started"))?;
+
+        let key = crypto::derive_key(
+            &verify.shared_secret,
+            "Pair-Verify-Encrypt-Salt",
+            "Pair-Verify-Encrypt-Info",
+        );
+        let data = crypto::open(&key, b"PV-Msg03", item(items, tlv::ENCRYPTED_DATA)?)?;
+        let controller = tlv::decode(&data)?;
+        let id = String::from_utf8(item(&controller, tlv::IDENTIFIER)?.to_vec())?;
+
+        let storage = lock(storage);
+        let pairing = storage
+            .pairing(&id)
+            .ok_or_else(|| anyhow!("Unknown controller {}", id))?;
+        let info = [
+            &verify.controller_key[..],
+            id.as_bytes(),
+            &verify.accessory_key,
+        ]
+        .concat();
+        verify_signature(
+            &pairing.public_key,
+            &info,
+            item(&controller, tlv::SIGNATURE)?,
+        )?;
+
+        info!("HomeKit controller {} verified", id);
+        Ok((Session::new(&verify.shared_secret), id))
+    }
+}
+
+/// Add, remove or list pairings. Only admins are allowed to do this.
+pub fn manage(body: &[u8], controller: &str, storage: &Mutex<Storage>) -> Vec<u8> {
+    match try_manage(body, controller, storage) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Could not change HomeKit pairings: {:#}", e);
+            error(2, tlv::ERROR_UNKNOWN)
+        }
+    }
+}
+
+fn try_manage(body: &[u8], controller: &str, storage: &Mutex<Storage>) -> Result<Vec<u8>> {
+    let items = tlv::decode(body)?;
+    let mut storage = lock(storage);
+    if !storage
+        .pairing(controller)
+        .is_some_and(|pairing| pairing.admin)
+    {
+        return Ok(error(2, tlv::ERROR_AUTHENTICATION));
+    }
+
+    match item(&items, tlv::METHOD)?.first().copied() {
+        Some(ADD_PAIRING) => {
+            let id = String::from_utf8(item(&items, tlv::IDENTIFIER)?.to_vec())?;
+            let public_key = item(&items, tlv::PUBLIC_KEY)?.to_vec();
+            let permissions = item(&items, tlv::PERMISSIONS)?.first().copied();
+
+            // The key of a known controller can not be replaced
+            if storage
+                .pairing(&id)
+                .is_some_and(|existing| existing.public_key != public_key)
+            {
+                return Ok(error(2, tlv::ERROR_UNKNOWN));
+            }
+            storage.add_pairing(Pairing {
+                id,
+                public_key,
+                admin: permissions == Some(ADMIN),
+            })?;
+            Ok(tlv::encode(&[(tlv::STATE, &[2])]))
+        }
+        Some(REMOVE_PAIRING) => {
+            let id = String::from_utf8(item(&items, tlv::IDENTIFIER)?.to_vec())?;
+            storage.remove_pairing(&id)?;
+            Ok(tlv::encode(&[(tlv::STATE, &[2])]))
+        }
+        Some(LIST_PAIRINGS) => {
+            let mut response = tlv::encode(&[(tlv::STATE, &[2])]);
+            for (index, pairing) in storage.pairings().iter().enumerate() {
+                if index > 0 {
+                    response.extend(tlv::encode(&[(tlv::SEPARATOR, &[])]));
+                }
+                let permissions = if pairing.admin { ADMIN } else { 0 };
+                response.extend(tlv::encode(&[
+                    (tlv::IDENTIFIER, pairing.id.as_bytes()),
+                    (tlv::PUBLIC_KEY, &pairing.public_key),
+                    (tlv::PERMISSIONS, &[permissions]),
+                ]));
+            }
+            Ok(response)
+        }
+        method => Err(anyhow!("Unknown pairings method {:?}", method)),
+    }
+}
+
+fn lock(storage: &Mutex<Storage>) -> MutexGuard<'_, Storage> {
+    storage.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,196 @@
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+
+/// 3072 bit group of RFC 5054
+const N: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+
+const G: u32 = 5;
+
+/// Name of the user in the SRP exchange of pair setup
+const USERNAME: &str = "Pair-Setup";
+
+/// Server side of the SRP-6a exchange (SHA-512, 3072 bit group) that proves the controller knows
+/// the setup code
+pub struct SrpServer {
+    n: BigUint,
+    g: BigUint,
+    salt: [u8; 16],
+    verifier: BigUint,
+    private_key: BigUint,
+    public_key: BigUint,
+}
+
+impl SrpServer {
+    pub fn new(setup_code: &str) -> Self {
+        let n = BigUint::parse_bytes(N.as_bytes(), 16).expect("SRP group is valid");
+        let g = BigUint::from(G);
+
+        let mut salt = [0; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let mut secret = [0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        // x = H(s | H(I | ":" | P)), v = g^x
+        let identity = hash(&[format!("{}:{}", USERNAME, setup_code).as_bytes()]);
+        let x = BigUint::from_bytes_be(&hash(&[&salt, &identity]));
+        let verifier = g.modpow(&x, &n);
+
+        // B = k * v + g^b
+        let k = BigUint::from_bytes_be(&hash(&[&n.to_bytes_be(), &pad(&g, &n)]));
+        let private_key = BigUint::from_bytes_be(&secret);
+        let public_key = (k * &verifier + g.modpow(&private_key, &n)) % &n;
+
+        SrpServer {
+            n,
+            g,
+            salt,
+            verifier,
+            private_key,
+            public_key,
+        }
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        pad(&self.public_key, &self.n)
+    }
+
+    /// Check the proof of the controller. Returns the shared session key and the proof of the
+    /// accessory if it is valid.
+    pub fn verify(&self, client_key: &[u8], client_proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let a = BigUint::from_bytes_be(client_key);
+        if (&a % &self.n) == BigUint::default() {
+            return None;
+        }
+
+        // A, B and S are padded to the length of N wherever they are hashed
+        let a_padded = pad(&a, &self.n);
+
+        // u = H(A | B), S = (A * v^u)^b, K = H(S)
+        let u = BigUint::from_bytes_be(&hash(&[&a_padded, &self.public_key()]));
+        let secret =
+            (a.clone() * self.verifier.modpow(&u, &self.n)).modpow(&self.private_key, &self.n);
+        let key = hash(&[&pad(&secret, &self.n)]);
+
+        // M1 = H(H(N) xor H(g) | H(I) | s | A | B | K)
+        let group: Vec<u8> = hash(&[&self.n.to_bytes_be()])
+            .iter()
+            .zip(hash(&[&self.g.to_bytes_be()]))
+            .map(|(n, g)| n ^ g)
+            .collect();
+        let expected = hash(&[
+            &group,
+            &hash(&[USERNAME.as_bytes()]),
+            &self.salt,
+            &a_padded,
+            &self.public_key(),
+            &key,
+        ]);
+        if expected != client_proof {
+            return None;
+        }
+
+        // M2 = H(A | M1 | K)
+        let proof = hash(&[&a_padded, client_proof, &key]);
+        Some((key, proof))
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Big endian bytes padded to the length of N
+fn pad(value: &BigUint, n: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let length = n.to_bytes_be().len();
+    let mut padded = vec![0; length.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Controller side of the exchange. Returns A, M1 and the session key K.
+    fn client(server: &SrpServer, setup_code: &str) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let n = &server.n;
+        let g = &server.g;
+        let b = BigUint::from_bytes_be(&server.public_key());
+
+        let mut secret = [0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let a = BigUint::from_bytes_be(&secret);
+        let a_public = pad(&g.modpow(&a, n), n);
+
+        // S = (B - k * g^x)^(a + u * x)
+        let k = BigUint::from_bytes_be(&hash(&[&n.to_bytes_be(), &pad(g, n)]));
+        let u = BigUint::from_bytes_be(&hash(&[&a_public, &server.public_key()]));
+        let identity = hash(&[format!("{}:{}", USERNAME, setup_code).as_bytes()]);
+        let x = BigUint::from_bytes_be(&hash(&[server.salt(), &identity]));
+        let base = (&b + n * &k - (k * g.modpow(&x, n)) % n) % n;
+        let secret = base.modpow(&(a + u * x), n);
+        let key = hash(&[&pad(&secret, n)]);
+
+        let group: Vec<u8> = hash(&[&n.to_bytes_be()])
+            .iter()
+            .zip(hash(&[&g.to_bytes_be()]))
+            .map(|(n, g)| n ^ g)
+            .collect();
+        let proof = hash(&[
+            &group,
+            &hash(&[USERNAME.as_bytes()]),
+            server.salt(),
+            &a_public,
+            &server.public_key(),
+            &key,
+        ]);
+        (a_public, proof, key)
+    }
+
+    #[test]
+    fn controller_with_the_setup_code_is_accepted() {
+        let server = SrpServer::new("123-45-678");
+        let (a, m1, key) = client(&server, "123-45-678");
+
+        let (server_key, m2) = server.verify(&a, &m1).expect("proof is accepted");
+        assert_eq!(server_key, key);
+        assert_eq!(m2, hash(&[&a, &m1, &key]));
+    }
+
+    #[test]
+    fn controller_with_a_wrong_setup_code_is_rejected() {
+        let server = SrpServer::new("123-45-678");
+        let (a, m1, _) = client(&server, "876-54-321");
+        assert!(server.verify(&a, &m1).is_none());
+    }
+
+    #[test]
+    fn public_key_that_is_a_multiple_of_n_is_rejected() {
+        let server = SrpServer::new("123-45-678");
+        let (_, m1, _) = client(&server, "123-45-678");
+        assert!(server.verify(&[0], &m1).is_none());
+        assert!(server.verify(&server.n.to_bytes_be(), &m1).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use ed25519_dalek::SigningKey;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::settings::Settings;
+
+/// Controller that has been paired with the accessory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pairing {
+    pub id: String,
+    pub public_key: Vec<u8>,
+    /// Admins can add and remove pairings
+    pub admin: bool,
+}
+
+/// Identity of the accessory and its pairings. They are kept in homekit.json next to the
+/// settings file so the accessory stays paired after a restart.
+#[derive(Serialize, Deserialize)]
+pub struct Storage {
+    /// Unique id of the accessory in the form of a MAC address
+    pub device_id: String,
+    secret_key: Vec<u8>,
+    pairings: Vec<Pairing>,
+    /// Wrong setup codes since the start
+    #[serde(skip)]
+    pub failed_attempts: u32,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Storage {
+    /// Read the storage or create a new identity if there is none
+    pub fn load() -> Result<Self> {
+        let path = Settings::resolve_path(Path::new("homekit.json"));
+        if path.exists() {
+            let mut storage: Storage = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            if storage.secret_key.len() != 32 {
+                return Err(anyhow!("Invalid secret key in {:?}", path));
+            }
+            storage.path = path;
+            return Ok(storage);
+        }
+
+        let mut id = [0u8; 6];
+        rand::thread_rng().fill_bytes(&mut id);
+        let mut secret_key = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut secret_key);
+
+        let storage = Storage {
+            device_id: id
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(":"),
+            secret_key,
+            pairings: Vec::new(),
+            failed_attempts: 0,
+            path,
+        };
+        info!("Created HomeKit identity {}", storage.device_id);
+        storage.save()?;
+        Ok(storage)
+    }
+
+    /// Long term key of the accessory
+    pub fn signing_key(&self) -> SigningKey {
+        let mut secret_key = [0; 32];
+        secret_key.copy_from_slice(&self.secret_key);
+        SigningKey::from_bytes(&secret_key)
+    }
+
+    pub fn is_paired(&self) -> bool {
+        !self.pairings.is_empty()
+    }
+
+    pub fn pairings(&self) -> &[Pairing] {
+        &self.pairings
+    }
+
+    pub fn pairing(&self, id: &str) -> Option<&Pairing> {
+        self.pairings.iter().find(|pairing| pairing.id == id)
+    }
+
+    /// Add a pairing or update the permissions of an existing one
+    pub fn add_pairing(&mut self, pairing: Pairing) -> Result<()> {
+        info!("Adding HomeKit pairing {}", pairing.id);
+        self.pairings.retain(|existing| existing.id != pairing.id);
+        self.pairings.push(pairing);
+        self.save()
+    }
+
+    /// Remove a pairing. Without any admin the accessory is unpaired completely.
+    pub fn remove_pairing(&mut self, id: &str) -> Result<()> {
+        info!("Removing HomeKit pairing {}", id);
+        self.pairings.retain(|pairing| pairing.id != id);
+        if !self.pairings.iter().any(|pairing| pairing.admin) {
+            self.pairings.clear();
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        // The file contains the secret key of the accessory
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
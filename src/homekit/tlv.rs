@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+// Types of the items used during pairing
+pub const METHOD: u8 = 0x00;
+pub const IDENTIFIER: u8 = 0x01;
+pub const SALT: u8 = 0x02;
+pub const PUBLIC_KEY: u8 = 0x03;
+pub const PROOF: u8 = 0x04;
+pub const ENCRYPTED_DATA: u8 = 0x05;
+pub const STATE: u8 = 0x06;
+pub const ERROR: u8 = 0x07;
+pub const SIGNATURE: u8 = 0x0A;
+pub const PERMISSIONS: u8 = 0x0B;
+pub const SEPARATOR: u8 = 0xFF;
+
+// Errors reported to the controller
+pub const ERROR_UNKNOWN: u8 = 0x01;
+pub const ERROR_AUTHENTICATION: u8 = 0x02;
+pub const ERROR_MAX_TRIES: u8 = 0x05;
+pub const ERROR_UNAVAILABLE: u8 = 0x06;
+
+/// Encode items as TLV8. Values longer than 255 bytes are split into fragments of the same
+/// type.
+pub fn encode(items: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (kind, value) in items {
+        if value.is_empty() {
+            data.extend_from_slice(&[*kind, 0]);
+        }
+        for fragment in value.chunks(255) {
+            data.push(*kind);
+            data.push(fragment.len() as u8);
+            data.extend_from_slice(fragment);
+        }
+    }
+    data
+}
+
+/// Decode TLV8 data. Consecutive fragments of the same type are joined.
+pub fn decode(data: &[u8]) -> Result<HashMap<u8, Vec<u8>>> {
+    let mut items: HashMap<u8, Vec<u8>> = HashMap::new();
+    let mut previous = None;
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let [kind, length, ..] = *rest else {
+            return Err(anyhow!("Truncated TLV item"));
+        };
+        let value = rest
+            .get(2..2 + length as usize)
+            .ok_or_else(|| anyhow!("Truncated TLV item"))?;
+
+        let item = items.entry(kind).or_default();
+        if previous != Some(kind) {
+            item.clear();
+        }
+        item.extend_from_slice(value);
+
+        previous = Some(kind);
+        rest = &rest[2 + length as usize..];
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_values_are_split_and_joined() {
+        let key: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let data = encode(&[(STATE, &[3]), (PUBLIC_KEY, &key), (PROOF, &[])]);
+
+        // 3 bytes state, fragments of 255, 255 and 90 bytes, empty proof
+        assert_eq!(data.len(), 3 + (2 + 255) * 2 + 2 + 90 + 2);
+        assert_eq!(data[3..5], [PUBLIC_KEY, 255]);
+        assert_eq!(data[260..262], [PUBLIC_KEY, 255]);
+        assert_eq!(data[517..519], [PUBLIC_KEY, 90]);
+
+        let items = decode(&data).unwrap();
+        assert_eq!(items[&STATE], [3]);
+        assert_eq!(items[&PUBLIC_KEY], key);
+        assert!(items[&PROOF].is_empty());
+    }
+
+    #[test]
+    fn value_of_exactly_255_bytes_is_one_fragment() {
+        let value = [7; 255];
+        let data = encode(&[(SALT, &value), (STATE, &[2])]);
+        assert_eq!(data.len(), 2 + 255 + 3);
+
+        let items = decode(&data).unwrap();
+        assert_eq!(items[&SALT], value);
+        assert_eq!(items[&STATE], [2]);
+    }
+
+    #[test]
+    fn items_of_the_same_type_are_only_joined_if_consecutive() {
+        let data = encode(&[
+            (IDENTIFIER, b"first"),
+            (SEPARATOR, &[]),
+            (IDENTIFIER, b"second"),
+        ]);
+        assert_eq!(decode(&data).unwrap()[&IDENTIFIER], b"second");
+    }
+
+    #[test]
+    fn truncated_items_are_rejected() {
+        let data = encode(&[(PUBLIC_KEY, &[1; 300])]);
+        assert!(decode(&data[..data.len() - 1]).is_err());
+        assert!(decode(&[STATE]).is_err());
+    }
+}
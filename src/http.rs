@@ -0,0 +1,263 @@
+use std::io::Read;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tracing::{debug, info, warn};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::WebSocket;
+
+use crate::control::{Command, Control};
+use crate::preview::Preview;
+use crate::settings::{EffectKind, EffectSettings, HttpSettings, Settings};
+
+#[derive(Deserialize)]
+struct ColorRequest {
+    color: [u8; 3],
+}
+
+#[derive(Deserialize)]
+struct BrightnessRequest {
+    brightness: f32,
+}
+
+/// Requests are small JSON objects. Longer bodies are rejected instead of reading them.
+const MAX_BODY_LENGTH: u64 = 64 * 1024;
+
+/// Web interface with the live preview
+const INDEX_HTML: &str = include_str!("../web/index.html");
+
+/// Start the HTTP server. It answers requests on its own thread and passes changes to the main
+/// loop.
+pub fn spawn(settings: &HttpSettings, control: Control, preview: Preview) -> Result<()> {
+    let server = Server::http(&settings.address)
+        .map_err(|e| anyhow!("Could not start HTTP server on {}: {}", settings.address, e))?;
+    info!("HTTP API listening on {}", settings.address);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            match (request.method(), request.url()) {
+                (Method::Get, "/") => show_page(request),
+                (Method::Get, "/api/preview") => stream_preview(request, &control, &preview),
+                _ => handle(request, &control),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut request: Request, control: &Control) {
+    let method = request.method().clone();
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    debug!("HTTP {} {}", method, path);
+
+    let mut body = String::new();
+    let response = match request
+        .as_reader()
+        .take(MAX_BODY_LENGTH + 1)
+        .read_to_string(&mut body)
+    {
+        Ok(length) if length as u64 > MAX_BODY_LENGTH => {
+            Err(Error::BadRequest("Request body is too long".to_string()))
+        }
+        Ok(_) => route(&method, &path, &body, control),
+        Err(e) => Err(Error::BadRequest(e.to_string())),
+    };
+
+    let (status, value) = match response {
+        Ok(value) => (200, value),
+        Err(Error::NotFound) => (404, json!({ "error": "Not found" })),
+        Err(Error::BadRequest(message)) => (400, json!({ "error": message })),
+        // Errors can contain contents of files, so the details are only logged
+        Err(Error::Internal(e)) => {
+            warn!("HTTP {} {} failed: {:#}", method, path, e);
+            (
+                500,
+                json!({ "error": "Internal error, see the log for details" }),
+            )
+        }
+        Err(Error::Rejected(e)) => {
+            warn!("HTTP {} {} rejected: {:#}", method, path, e);
+            (
+                400,
+                json!({ "error": "Invalid request, see the log for details" }),
+            )
+        }
+    };
+
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("Content type header is valid");
+    let response = Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        debug!("Could not send HTTP response: {}", e);
+    }
+}
+
+fn show_page(request: Request) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..])
+        .expect("Content type header is valid");
+    let response = Response::from_string(INDEX_HTML).with_header(header);
+    if let Err(e) = request.respond(response) {
+        debug!("Could not send HTTP response: {}", e);
+    }
+}
+
+/// Upgrade the request to a WebSocket and stream the preview on its own thread
+fn stream_preview(request: Request, control: &Control, preview: &Preview) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_bytes()));
+    let Some(accept) = key else {
+        let response =
+            Response::from_string(json!({ "error": "Expected a WebSocket" }).to_string())
+                .with_status_code(400);
+        if let Err(e) = request.respond(response) {
+            debug!("Could not send HTTP response: {}", e);
+        }
+        return;
+    };
+
+    // The upgrade adds the Connection and Upgrade headers itself
+    let header = Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept.as_bytes())
+        .expect("Accept header is valid");
+    let response = Response::empty(StatusCode(101)).with_header(header);
+    let stream = request.upgrade("websocket", response);
+
+    let control = control.clone();
+    let preview = preview.clone();
+    thread::spawn(move || {
+        info!("Preview viewer connected");
+        let socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        if let Err(e) = preview.stream(socket, &control) {
+            debug!("Preview stream ended: {:#}", e);
+        }
+        info!("Preview viewer disconnected");
+    });
+}
+
+/// Reasons why a request failed
+enum Error {
+    NotFound,
+    BadRequest(String),
+    /// The request is invalid for a reason that is only logged
+    Rejected(anyhow::Error),
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Internal(e)
+    }
+}
+
+fn route(method: &Method, path: &str, body: &str, control: &Control) -> Result<Value, Error> {
+    match (method, path) {
+        (Method::Get, "/api/state") => {}
+        (Method::Post, "/api/on") => control.send(Command::SetEnabled(true))?,
+        (Method::Post, "/api/off") => control.send(Command::SetEnabled(false))?,
+        (Method::Put, "/api/brightness") => {
+            let request: BrightnessRequest = parse(body)?;
+            if !(0.0..=1.0).contains(&request.brightness) {
+                return Err(Error::BadRequest(
+                    "Brightness must be between 0 and 1".to_string(),
+                ));
+            }
+            control.send(Command::SetBrightness(request.brightness))?;
+        }
+        (Method::Put, "/api/color") => {
+            let request: ColorRequest = parse(body)?;
+            let mut effect = EffectSettings::new(EffectKind::StaticColor);
+            effect.colors = vec![request.color];
+            control.send(Command::SetEffect(Some(effect)))?;
+        }
+        (Method::Put, "/api/effect") => {
+            let effect: EffectSettings = parse(body)?;
+            effect
+                .validate()
+                .map_err(|e| Error::BadRequest(format!("{:#}", e)))?;
+            control.send(Command::SetEffect(Some(effect)))?;
+        }
+        (Method::Delete, "/api/effect") | (Method::Delete, "/api/color") => {
+            control.send(Command::SetEffect(None))?;
+        }
+        (Method::Get, "/api/settings") => return Ok(to_value(&control.state().settings)?),
+        (Method::Patch, "/api/settings") => {
+            let patch: Value = parse(body)?;
+            let mut settings = to_value(&control.state().settings)?;
+            merge(&mut settings, patch);
+            let settings: Settings = serde_json::from_value(settings)
+                .map_err(|e| Error::BadRequest(format!("Invalid settings: {}", e)))?;
+            if file_paths(&settings) != file_paths(&control.state().settings) {
+                return Err(Error::BadRequest(
+                    "Paths can only be changed in the settings file".to_string(),
+                ));
+            }
+
+            // Applying settings keeps the current brightness, so a new one is set on its own
+            let brightness = settings.color_calibration.brightness;
+            if brightness != control.state().brightness {
+                control.send(Command::SetBrightness(brightness))?;
+            }
+            control.apply_settings(settings).map_err(Error::Rejected)?;
+            return Ok(to_value(&control.state().settings)?);
+        }
+        _ => return Err(Error::NotFound),
+    }
+
+    // Changes are applied by the main loop, the state shows the latest known values
+    Ok(to_value(&control.state())?)
+}
+
+/// Files the settings refer to. Remote clients must not change them because rustylight usually
+/// runs as root and would read any file.
+fn file_paths(settings: &Settings) -> Value {
+    json!([
+        settings.lut.as_ref().map(|lut| &lut.path),
+        settings.auto_off.placeholder_image,
+        settings.audio.path,
+        settings.socket.as_ref().map(|socket| &socket.path),
+    ])
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| Error::BadRequest(format!("Invalid request: {}", e)))
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// Applies a JSON merge patch (RFC 7386). Objects are merged recursively, null removes a value
+/// and everything else is replaced.
+fn merge(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use smart_leds::RGB16;
+use tracing::info;
+
+use crate::color;
+use crate::settings::LutInterpolation;
+
+type Color = [f32; 3];
+
+/// 3D lookup table loaded from a .cube file (Adobe/Resolve format). Each LED color is looked up
+/// in the table and interpolated between the surrounding grid points.
+pub struct Lut3d {
+    size: usize,
+    domain_min: Color,
+    domain_max: Color,
+    /// Grid points with red changing fastest, then green, then blue
+    table: Vec<Color>,
+    interpolation: LutInterpolation,
+}
+
+impl Lut3d {
+    /// Read a .cube file
+    pub fn load(path: &Path, interpolation: LutInterpolation) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read LUT file {:?}", path))?;
+        let lut = Self::parse(&content, interpolation)
+            .with_context(|| format!("Invalid LUT file {:?}", path))?;
+        info!(
+            "Loaded {size}x{size}x{size} LUT from {:?}",
+            path,
+            size = lut.size
+        );
+        Ok(lut)
+    }
+
+    /// Parse the content of a .cube file
+    pub fn parse(content: &str, interpolation: LutInterpolation) -> Result<Self> {
+        let mut size: Option<usize> = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table: Vec<Color> = Vec::new();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let line_error = || anyhow!("Could not parse line {}", number + 1);
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => bail!("1D LUTs are not supported"),
+                "LUT_3D_SIZE" => {
+                    let value: usize = words
+                        .next()
+                        .and_then(|w| w.parse().ok())
+                        .ok_or_else(line_error)?;
+                    if !(2..=256).contains(&value) {
+                        bail!("LUT_3D_SIZE must be between 2 and 256 but is {}", value);
+                    }
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = parse_triple(words).ok_or_else(line_error)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(words).ok_or_else(line_error)?,
+                // The same range for every channel, as written by Resolve
+                "LUT_3D_INPUT_RANGE" | "LUT_1D_INPUT_RANGE" => {
+                    let (min, max) = parse_range(words).ok_or_else(line_error)?;
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }
+                _ => {
+                    let values = parse_triple(line.split_whitespace()).ok_or_else(line_error)?;
+                    table.push(values);
+                }
+            }
+        }
+
+        let size = size.ok_or_else(|| anyhow!("LUT_3D_SIZE is missing"))?;
+        if table.len() != size.pow(3) {
+            bail!(
+                "Expected {} entries for LUT_3D_SIZE {} but found {}",
+                size.pow(3),
+                size,
+                table.len()
+            );
+        }
+        if (0..3).any(|channel| domain_max[channel] <= domain_min[channel]) {
+            bail!("DOMAIN_MAX must be larger than DOMAIN_MIN");
+        }
+
+        Ok(Lut3d {
+            size,
+            domain_min,
+            domain_max,
+            table,
+            interpolation,
+        })
+    }
+
+    /// Look up all LEDs in place
+    pub fn apply(&self, leds: &mut [RGB16]) {
+        for led in leds.iter_mut() {
+            *led = color::from_unit(self.lookup(color::to_unit(*led)));
+        }
+    }
+
+    /// Look up a color with channels in the range 0..1
+    pub fn lookup(&self, color: Color) -> Color {
+        let max_index = (self.size - 1) as f32;
+
+        // Position of the color in the grid
+        let mut base = [0usize; 3];
+        let mut fraction = [0f32; 3];
+        for channel in 0..3 {
+            let normalized = (color[channel] - self.domain_min[channel])
+                / (self.domain_max[channel] - self.domain_min[channel]);
+            let position = (normalized * max_index).clamp(0.0, max_index);
+
+            // The last grid point has no upper neighbour
+            base[channel] = (position.floor() as usize).min(self.size - 2);
+            fraction[channel] = position - base[channel] as f32;
+        }
+
+        let corner = |r: usize, g: usize, b: usize| -> Color {
+            self.table[(base[0] + r) + (base[1] + g) * self.size + (base[2] + b) * self.size.pow(2)]
+        };
+
+        match self.interpolation {
+            LutInterpolation::Trilinear => trilinear(&corner, fraction),
+            LutInterpolation::Tetrahedral => tetrahedral(&corner, fraction),
+        }
+    }
+}
+
+/// Interpolates between all eight corners of the surrounding cube
+fn trilinear(corner: &dyn Fn(usize, usize, usize) -> Color, [fr, fg, fb]: [f32; 3]) -> Color {
+    let lerp = |a: Color, b: Color, t: f32| -> Color {
+        [
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+        ]
+    };
+
+    let c00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fr);
+    let c10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fr);
+    let c01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fr);
+    let c11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fr);
+
+    let c0 = lerp(c00, c10, fg);
+    let c1 = lerp(c01, c11, fg);
+
+    lerp(c0, c1, fb)
+}
+
+/// Splits the surrounding cube into six tetrahedra and interpolates between the four corners of
+/// the one containing the color. This preserves the grey axis better than trilinear
+/// interpolation.
+fn tetrahedral(corner: &dyn Fn(usize, usize, usize) -> Color, [fr, fg, fb]: [f32; 3]) -> Color {
+    let c000 = corner(0, 0, 0);
+    let c111 = corner(1, 1, 1);
+
+    // The two corners between c000 and c111 and the weights of the four corners
+    let (c1, c2, weights) = if fr > fg {
+        if fg > fb {
+            (
+                corner(1, 0, 0),
+                corner(1, 1, 0),
+                [1.0 - fr, fr - fg, fg - fb, fb],
+            )
+        } else if fr > fb {
+            (
+                corner(1, 0, 0),
+                corner(1, 0, 1),
+                [1.0 - fr, fr - fb, fb - fg, fg],
+            )
+        } else {
+            (
+                corner(0, 0, 1),
+                corner(1, 0, 1),
+                [1.0 - fb, fb - fr, fr - fg, fg],
+            )
+        }
+    } else if fb > fg {
+        (
+            corner(0, 0, 1),
+            corner(0, 1, 1),
+            [1.0 - fb, fb - fg, fg - fr, fr],
+        )
+    } else if fb > fr {
+        (
+            corner(0, 1, 0),
+            corner(0, 1, 1),
+            [1.0 - fg, fg - fb, fb - fr, fr],
+        )
+    } else {
+        (
+            corner(0, 1, 0),
+            corner(1, 1, 0),
+            [1.0 - fg, fg - fr, fr - fb, fb],
+        )
+    };
+
+    let mut result = [0.0; 3];
+    for (channel, value) in result.iter_mut().enumerate() {
+        *value = c000[channel] * weights[0]
+            + c1[channel] * weights[1]
+            + c2[channel] * weights[2]
+            + c111[channel] * weights[3];
+    }
+    result
+}
+
+fn parse_range<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<(f32, f32)> {
+    Some((words.next()?.parse().ok()?, words.next()?.parse().ok()?))
+}
+
+fn parse_triple<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<Color> {
+    let mut triple = [0.0; 3];
+    for value in triple.iter_mut() {
+        *value = words.next()?.parse().ok()?;
+    }
+    Some(triple)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// .cube content of a table with the given size that maps every color to itself
+    fn identity(size: usize, header: &str) -> String {
+        let mut content = format!("{}\nLUT_3D_SIZE {}\n", header, size);
+        let max = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    content +=
+                        &format!("{} {} {}\n", r as f32 / max, g as f32 / max, b as f32 / max);
+                }
+            }
+        }
+        content
+    }
+
+    fn parse_error(content: &str) -> String {
+        match Lut3d::parse(content, LutInterpolation::Trilinear) {
+            Ok(_) => panic!("Invalid LUT was accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn assert_close(actual: Color, expected: Color) {
+        for channel in 0..3 {
+            assert!(
+                (actual[channel] - expected[channel]).abs() < 1e-4,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn parses_comments_title_and_domain() {
+        let header = "# Created by hand\nTITLE \"Test\"\n\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2";
+        let lut = Lut3d::parse(&identity(2, header), LutInterpolation::Trilinear).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.domain_min, [0.0; 3]);
+        assert_eq!(lut.domain_max, [2.0; 3]);
+        assert_eq!(lut.table.len(), 8);
+        assert_eq!(lut.table[1], [1.0, 0.0, 0.0]);
+        assert_eq!(lut.table[2], [0.0, 1.0, 0.0]);
+        assert_eq!(lut.table[4], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn parses_input_range() {
+        for keyword in ["LUT_3D_INPUT_RANGE", "LUT_1D_INPUT_RANGE"] {
+            let header = format!("{} 0.5 4", keyword);
+            let lut = Lut3d::parse(&identity(2, &header), LutInterpolation::Trilinear).unwrap();
+            assert_eq!(lut.domain_min, [0.5; 3]);
+            assert_eq!(lut.domain_max, [4.0; 3]);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_entry_count() {
+        let mut content = identity(3, "");
+        content += "0 0 0\n";
+        let error = parse_error(&content);
+        assert!(error.contains("Expected 27 entries"), "{}", error);
+    }
+
+    #[test]
+    fn reports_only_the_line_number() {
+        let content = identity(2, "LUT_3D_INPUT_RANGE 0 secret");
+        assert_eq!(parse_error(&content), "Could not parse line 1");
+    }
+
+    #[test]
+    fn rejects_missing_size_and_empty_domain() {
+        assert_eq!(parse_error("0 0 0\n"), "LUT_3D_SIZE is missing");
+        let content = identity(2, "DOMAIN_MIN 1 0 0\nDOMAIN_MAX 1 1 1");
+        assert_eq!(
+            parse_error(&content),
+            "DOMAIN_MAX must be larger than DOMAIN_MIN"
+        );
+    }
+
+    #[test]
+    fn identity_returns_its_input() {
+        let colors = [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5, 0.5, 0.5],
+            [0.1, 0.7, 0.3],
+            [0.9, 0.2, 0.6],
+            [0.3, 0.3, 0.8],
+        ];
+        for interpolation in [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral] {
+            let lut = Lut3d::parse(&identity(5, ""), interpolation).unwrap();
+            for color in colors {
+                assert_close(lut.lookup(color), color);
+            }
+        }
+    }
+}
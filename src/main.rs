@@ -1,176 +1,440 @@
 #![allow(dead_code)]
 #![allow(unreachable_code)]
 
+mod audio;
+mod capture;
+mod cli;
+mod color;
+mod color_calibration;
+mod color_space;
+mod control;
+mod dithering;
+mod effects;
+mod homekit;
+mod http;
+mod lut;
+mod mqtt;
+mod output;
+mod perspective;
+mod power;
+mod preview;
+mod resampler;
+mod sampling;
+mod scene_cut;
 mod settings;
+mod settings_watcher;
+mod signal_detector;
+mod signals;
+mod smoothing;
+mod socket;
+mod socket_path;
+mod source;
+mod tone_mapping;
 mod translation_engine;
 mod video;
 
-use std::time::Duration;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use audio::AudioVisualizer;
+use capture::Capture;
+use clap::Parser;
+use cli::Cli;
+use control::{Command, Control};
+#[cfg(feature = "highgui")]
+use opencv::highgui;
+use opencv::{core::Vector, imgcodecs};
+use output::{LedSink, Output, OutputMessage, TerminalSink, Ws2812Sink};
+use preview::{Preview, PreviewSink};
+use settings::{EffectKind, EffectSettings, Mode, OutputDevice, Settings};
+use smart_leds::RGB8;
+use source::LedSource;
+use video::Video;
 
-use anyhow::Result;
-use opencv::{
-    core::{Scalar, Vec3b, CV_8UC3},
-    highgui,
-    prelude::*,
-    videoio::VideoCapture,
-};
-use settings::Settings;
+use tracing::{info, warn};
+use tracing_subscriber::FmtSubscriber;
 
-use translation_engine::TranslationEngine;
+/// How often commands without the main loop check whether they have been interrupted
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(100);
 
-use tracing::{debug, error, info};
-use tracing_subscriber::FmtSubscriber;
-use video::Video;
+/// Open the configured output device
+fn create_sink(output: OutputDevice, led_count: i32) -> Result<Box<dyn LedSink>> {
+    match output {
+        OutputDevice::Ws2812 => Ok(Box::new(Ws2812Sink::new(led_count, 18)?)),
+        OutputDevice::Terminal => Ok(Box::new(TerminalSink)),
+    }
+}
 
-use std::thread::sleep;
+/// Start the output thread that smooths and calibrates the colors and writes them to the
+/// lightstrip
+fn spawn_output(settings: &Settings, preview: &Preview) -> Result<Output> {
+    let (output, led_count) = (settings.output, settings.led_count);
+    let preview = preview.clone();
+    Output::spawn(settings, move || {
+        let sink = create_sink(output, led_count)?;
+        Ok(Box::new(PreviewSink::new(sink, preview)) as Box<dyn LedSink>)
+    })
+}
+
+/// The audio visualizer replaces the video capture in audio mode
+fn create_source(settings: &Settings, preview: &Preview) -> Result<Box<dyn LedSource>> {
+    match settings.mode {
+        Mode::Audio => Ok(Box::new(AudioVisualizer::new(settings)?)),
+        Mode::Video | Mode::Hybrid => Ok(Box::new(Capture::new(settings, preview.clone())?)),
+    }
+}
+
+/// Start or stop the effect that has been chosen remotely
+fn send_effect(output: &mut Output, effect: Option<EffectSettings>) -> Result<()> {
+    let priority = effects::MANUAL_PRIORITY;
+    match effect {
+        Some(settings) => output.send(OutputMessage::StartEffect { settings, priority }),
+        None => output.send(OutputMessage::StopEffect { priority }),
+    }
+}
 
-use smart_leds::{SmartLedsWrite, RGB8};
-use ws281x_rpi::Ws2812Rpi;
+/// Everything the main loop works with
+struct App {
+    settings: Settings,
+    source: Box<dyn LedSource>,
+    output: Output,
+    control: Control,
+    preview: Preview,
+}
 
-fn vec3b_to_smaller_rgb8(temp: &Vec<Vec3b>, pixel_per_led: i32) -> Vec<RGB8> {
-    let mut pixels: Vec<RGB8> = Vec::new();
+impl App {
+    /// Handle a command of a remote interface. Returns false when rustylight shall stop.
+    fn handle(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::SetEnabled(enabled) => {
+                self.set_enabled(enabled)?;
+                self.control.update(|state| state.enabled = enabled);
+            }
+            Command::SetBrightness(brightness) => {
+                self.settings.color_calibration.brightness = brightness;
+                let calibration = self.settings.color_calibration;
+                self.output.send(OutputMessage::Calibration(calibration))?;
+                self.control.update(|state| {
+                    state.brightness = brightness;
+                    state.settings.color_calibration = calibration;
+                });
+            }
+            Command::SetEffect(effect) => {
+                send_effect(&mut self.output, effect.clone())?;
+                self.control.update(|state| state.effect = effect);
+            }
+            Command::ApplySettings(settings, reply) => {
+                let result = self.apply_settings(*settings);
+                if let Err(e) = &result {
+                    warn!("Could not apply settings: {:#}", e);
+                }
+                let _ = reply.send(result);
+            }
+            Command::Shutdown => return Ok(false),
+        }
+        Ok(true)
+    }
 
-    for chunk in temp.chunks(pixel_per_led as usize) {
-        let mut mean_b = 0;
-        let mut mean_g = 0;
-        let mut mean_r = 0;
+    /// Switch to new settings without a restart. If anything can not be set up the previous
+    /// settings stay active.
+    fn apply_settings(&mut self, mut settings: Settings) -> Result<()> {
+        settings.validate()?;
+        // The brightness is kept like the other values that have been changed at runtime
+        let state = self.control.state();
+        settings.color_calibration.brightness = state.brightness;
+        info!("Applying settings: {:?}", settings);
+
+        // The remote interfaces and logging are only set up at the start
+        let restart_only = |settings: &Settings| {
+            serde_json::to_value((
+                settings.log_level,
+                &settings.http,
+                &settings.mqtt,
+                &settings.homekit,
+                &settings.socket,
+            ))
+            .ok()
+        };
+        if restart_only(&settings) != restart_only(&self.settings) {
+            warn!(
+                "Changes of log_level, http, mqtt, homekit and socket are applied after a restart"
+            );
+        }
 
-        for elem in chunk.iter() {
-            mean_b += elem[0] as u32;
-            mean_g += elem[1] as u32;
-            mean_r += elem[2] as u32;
+        // Audio mode uses a different source. It replaces the current one once everything else
+        // has been set up.
+        let is_audio = |settings: &Settings| settings.mode == Mode::Audio;
+        let replacement = if is_audio(&settings) != is_audio(&self.settings) {
+            Some(create_source(&settings, &self.preview)?)
+        } else {
+            self.source.reconfigure(&settings)?;
+            None
+        };
+
+        // The lightstrip driver has to be set up again for a different amount of LEDs or another
+        // output device
+        let respawn = settings.led_count != self.settings.led_count
+            || settings.output != self.settings.output;
+        let result = if respawn {
+            self.respawn_output(&settings)
+        } else {
+            self.output.reconfigure(&settings)
+        };
+        if let Err(e) = result {
+            if replacement.is_none() {
+                self.source.reconfigure(&self.settings)?;
+            }
+            return Err(e);
+        }
+        if let Some(source) = replacement {
+            self.source = source;
         }
 
-        mean_b /= pixel_per_led as u32;
-        mean_g /= pixel_per_led as u32;
-        mean_r /= pixel_per_led as u32;
+        // Restore what has been changed at runtime
+        self.set_enabled(state.enabled)?;
+        if respawn {
+            send_effect(&mut self.output, state.effect)?;
+        }
 
-        pixels.push(RGB8 {
-            r: mean_r as u8,
-            g: mean_g as u8,
-            b: mean_b as u8,
-        })
+        self.control
+            .update(|state| state.settings = settings.clone());
+        self.settings = settings;
+        Ok(())
     }
 
-    pixels
-}
+    /// Turn the source and the LEDs on or off
+    fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.source.set_enabled(enabled, &mut self.output)?;
+        self.output.send(OutputMessage::Enabled(enabled))
+    }
 
-/// If a size-zero has been received wait for half a second and try again
-fn wait_for_frame(v: &mut VideoCapture, f: &mut Mat) {
-    loop {
-        v.read(f).expect("Could not read frame.");
-        let size = f.size().expect("Could not get size from frame");
-        if size.width == 0 || size.height == 0 {
-            debug!("Input with invalid size. Waiting...");
-            sleep(Duration::from_millis(500));
-            continue;
+    /// Process the input and handle the commands of the remote interfaces until the input ends,
+    /// rustylight shall stop or an error occurs
+    fn run(&mut self, commands: &Receiver<Command>) -> Result<()> {
+        loop {
+            for command in commands.try_iter() {
+                if !self.handle(command)? {
+                    return Ok(());
+                }
+            }
+
+            if !self.source.process(&mut self.output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Fade the LEDs out, turn them off and release the input
+    fn shutdown(mut self) -> Result<()> {
+        let output = self.output.shutdown();
+        if let Err(e) = &output {
+            // The output thread has failed, so the LEDs are turned off directly
+            warn!("Could not fade out the LEDs: {:#}", e);
+            turn_off(&self.settings)?;
+        }
+        let source = self.source.release();
+        info!("Rustylight stopped");
+        output.and(source)
+    }
+
+    fn respawn_output(&mut self, settings: &Settings) -> Result<()> {
+        self.output.stop()?;
+        match spawn_output(settings, &self.preview) {
+            Ok(output) => {
+                self.output = output;
+                Ok(())
+            }
+            Err(e) => {
+                self.output = spawn_output(&self.settings, &self.preview)?;
+                Err(e)
+            }
         }
-        break;
     }
 }
 
 fn main() -> Result<()> {
-    dotenvy::dotenv()?;
+    // The .env file is optional
+    let _ = dotenvy::dotenv();
+
+    let mut cli = Cli::parse();
+    if let Some(path) = &cli.config {
+        Settings::set_path(path.clone());
+    }
+
+    match cli.command.take().unwrap_or(cli::Command::Run) {
+        cli::Command::Run => run(&cli, init(&cli)?),
+        cli::Command::CheckConfig => check_config(&cli),
+        cli::Command::PrintDefaultConfig => {
+            print!("{}", Settings::default_toml()?);
+            Ok(())
+        }
+        cli::Command::ListDevices => list_devices(),
+        cli::Command::TestPattern { duration } => test_pattern(&init(&cli)?, duration),
+        cli::Command::Snapshot { path } => snapshot(&init(&cli)?, &path),
+        cli::Command::Calibrate => calibrate(&init(&cli)?),
+        cli::Command::Off => turn_off(&init(&cli)?),
+    }
+}
 
-    let settings = Settings::new()?;
+/// Read the settings, apply the command line overrides and set up logging
+fn init(cli: &Cli) -> Result<Settings> {
+    let mut settings = Settings::new()?;
+    cli.apply(&mut settings);
 
     let subscriber = FmtSubscriber::builder()
         .with_max_level(settings.log_level)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    Ok(settings)
+}
 
-    info!(
-        "Rustylight will use the following settings: {:?}",
-        &settings
-    );
+/// Read the settings and set up everything that does not need the hardware
+fn check_config(cli: &Cli) -> Result<()> {
+    let settings = cli.load_settings()?;
 
-    #[cfg(feature = "highgui")]
-    {
-        highgui::named_window("original", highgui::WINDOW_NORMAL)?;
-        highgui::named_window("frame", highgui::WINDOW_NORMAL)?;
+    settings.validate()?;
+    Output::check(&settings)?;
+    if let Some(homekit) = &settings.homekit {
+        homekit::validate_setup_code(&homekit.setup_code)?;
     }
 
-    let mut orig_frame = Mat::default();
+    println!("{:?} is valid", Settings::path());
+    Ok(())
+}
 
-    let mut input = Video::new(&settings)?;
-    //let mut cam =
-    //    videoio::VideoCapture::from_file("/home/max/Downloads/test_vid_02.mp4", videoio::CAP_ANY)?;
+fn list_devices() -> Result<()> {
+    println!("Video devices (video_device):");
+    match Video::list() {
+        Ok(devices) if !devices.is_empty() => {
+            for (index, name) in devices {
+                println!("  {:<4} {}", index, name);
+            }
+        }
+        Ok(_) => println!("  none found"),
+        Err(e) => println!("  could not list video devices: {}", e),
+    }
 
-    // Get the size of the video feed
-    wait_for_frame(&mut input, &mut orig_frame);
-    let size = orig_frame.size()?;
-    info!(
-        "Reading video data with resolution widht: {}, height: {}",
-        size.width, size.height
-    );
+    println!("Audio devices (audio.device):");
+    let devices = audio::list_devices();
+    if devices.is_empty() {
+        println!("  none found");
+    }
+    for (input, device) in devices {
+        println!("  {:<12} {}", format!("{:?}", input), device);
+    }
+    Ok(())
+}
 
-    // The border must be smaller than half of width and height
-    if size.width / 2 < settings.capture_area_size || size.height < settings.capture_area_size {
-        info!(
-            "Border is too thick! The following must hold: border < width/2 && border < height/2"
-        );
-        return Ok(());
-    }
-
-    // Set the width of "regions"
-    let region_width = size.width - settings.capture_area_size;
-    let region_height = size.height - settings.capture_area_size;
-
-    let pixel_per_led = ((2 * region_height) + (2 * region_width)) / settings.led_count;
-    info!("Pixels per LED: {}", pixel_per_led);
-
-    // Create the target target_frame
-    // This will hold the data that shall be sent to the leds
-    //let mut target_frame = Mat::new_rows_cols_with_default(
-    //    1,
-    //    (2 * region_height) + (2 * region_width),
-    //    orig_frame.typ(),
-    //    Scalar::all(0.0),
-    //)?;
-
-    let mut target_vec: Vec<Vec3b> =
-        Vec::with_capacity(((2 * region_height) + (2 * region_height)) as usize);
-
-    let mut led_values: Vec<RGB8> = Vec::with_capacity(settings.led_count as usize);
-    let mut ws = Ws2812Rpi::new(settings.led_count, 18)?;
-
-    // Translation funcs that shall be applied to each frame
-    let translation_funcs = TranslationEngine::new(
-        settings.start_corner,
-        settings.direction,
-        region_width,
-        region_height,
-        settings.capture_area_size,
+/// Show the test pattern effect until the duration has passed or rustylight is interrupted
+fn test_pattern(settings: &Settings, duration: Option<u64>) -> Result<()> {
+    let interrupted = signals::shutdown_flag()?;
+    let mut output = spawn_output(settings, &Preview::new())?;
+    send_effect(
+        &mut output,
+        Some(EffectSettings::new(EffectKind::TestPattern)),
+    )?;
+    info!(
+        "Showing the test pattern. The first LED is white, the top edge red, the right edge \
+         green, the bottom edge blue and the left edge yellow"
     );
 
-    info!("----- STARTING MAIN LOOP -----");
+    let end = duration.map(|seconds| Instant::now() + Duration::from_secs(seconds));
     loop {
-        wait_for_frame(&mut input, &mut orig_frame);
-
-        for func in translation_funcs.iter() {
-            func(&orig_frame, &mut target_vec)?;
+        let expired = matches!(end, Some(end) if Instant::now() >= end);
+        if expired || interrupted.load(Ordering::Relaxed) {
+            break;
         }
+        sleep(SIGNAL_INTERVAL);
+    }
 
-        ws.write(
-            vec3b_to_smaller_rgb8(&target_vec, pixel_per_led)
-                .iter()
-                .cloned(),
-        )
-        .unwrap();
-
-        #[cfg(feature = "highgui")]
-        {
-            highgui::imshow("original", &orig_frame)?;
-            highgui::imshow("frame", &target_frame)?;
-
-            let key = highgui::wait_key(1)?;
-            if key == 113 {
-                // quit with q
-                break;
-            }
-        }
+    output.shutdown()
+}
+
+/// Save a frame of the video input to an image file
+fn snapshot(settings: &Settings, path: &Path) -> Result<()> {
+    let frame = Capture::snapshot(settings)?;
+    let Some(file_name) = path.to_str() else {
+        bail!("Invalid snapshot path {:?}", path);
+    };
+    if !imgcodecs::imwrite(file_name, &frame, &Vector::new())? {
+        bail!("Could not write snapshot to {:?}", path);
     }
+    info!("Saved snapshot to {:?}", path);
+    Ok(())
+}
 
+/// Detect the corners of the filmed screen and save them to the settings file
+fn calibrate(settings: &Settings) -> Result<()> {
+    let snapshot = Capture::snapshot(settings)?;
+    // Only the corners are saved, not the command line overrides
+    let mut saved = Settings::load()?;
+    perspective::calibrate(&snapshot, &mut saved)?;
     Ok(())
 }
+
+/// Write black to all LEDs
+fn turn_off(settings: &Settings) -> Result<()> {
+    let mut sink = create_sink(settings.output, settings.led_count)?;
+    sink.write(&vec![RGB8::default(); settings.led_count.max(0) as usize])?;
+    info!("Turned the LEDs off");
+    Ok(())
+}
+
+/// Run the ambilight until the source ends or rustylight is stopped by a signal
+fn run(cli: &Cli, settings: Settings) -> Result<()> {
+    settings.validate()?;
+    info!(
+        "Rustylight will use the following settings: {:?}",
+        &settings
+    );
+
+    #[cfg(feature = "highgui")]
+    {
+        highgui::named_window("original", highgui::WINDOW_NORMAL)?;
+        highgui::named_window("frame", highgui::WINDOW_NORMAL)?;
+    }
+
+    // Remote interfaces pass their commands to the main loop
+    let (control, commands) = Control::new(&settings);
+    let overrides = cli.clone();
+    let reload = move || overrides.load_settings();
+    signals::spawn(control.clone(), reload.clone())?;
+    if let Err(e) = settings_watcher::spawn(control.clone(), reload) {
+        warn!(
+            "Changes of the settings file are only applied on SIGHUP: {:#}",
+            e
+        );
+    }
+    let preview = Preview::new();
+    if let Some(http) = &settings.http {
+        http::spawn(http, control.clone(), preview.clone())?;
+    }
+    if let Some(mqtt) = &settings.mqtt {
+        mqtt::spawn(mqtt, control.clone())?;
+    }
+    if let Some(homekit) = &settings.homekit {
+        homekit::spawn(homekit, control.clone())?;
+    }
+    if let Some(socket) = &settings.socket {
+        socket::spawn(socket, control.clone())?;
+    }
+
+    let mut app = App {
+        output: spawn_output(&settings, &preview)?,
+        source: create_source(&settings, &preview)?,
+        settings,
+        control,
+        preview,
+    };
+
+    info!("----- STARTING MAIN LOOP -----");
+    // The LEDs are turned off however the main loop ends
+    let result = app.run(&commands);
+    let shutdown = app.shutdown();
+    result.and(shutdown)
+}
@@ -0,0 +1,260 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::control::{Command, Control, State};
+use crate::effects::{self, Summary};
+use crate::settings::{EffectKind, EffectSettings, MqttSettings};
+
+/// How often the state is checked for changes that have to be published
+const STATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wait before reconnecting after the connection to the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Amount of requests that can be queued for the broker
+const CAPACITY: usize = 32;
+
+/// Command of Home Assistant for a light with the JSON schema
+#[derive(Deserialize)]
+struct LightCommand {
+    state: Option<String>,
+    brightness: Option<u8>,
+    color: Option<Rgb>,
+    effect: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+struct Topics {
+    command: String,
+    state: String,
+    availability: String,
+    discovery: String,
+    /// Home Assistant announces on this topic that it has been started
+    status: String,
+}
+
+impl Topics {
+    fn new(settings: &MqttSettings) -> Self {
+        Topics {
+            command: format!("{}/set", settings.id),
+            state: format!("{}/state", settings.id),
+            availability: format!("{}/availability", settings.id),
+            discovery: format!("{}/light/{}/config", settings.discovery_prefix, settings.id),
+            status: format!("{}/status", settings.discovery_prefix),
+        }
+    }
+}
+
+/// Connect to the MQTT broker and announce rustylight as a light to Home Assistant. Commands are
+/// passed to the main loop and the state is published whenever it changes.
+pub fn spawn(settings: &MqttSettings, control: Control) -> Result<()> {
+    let topics = Topics::new(settings);
+    let mut options = MqttOptions::new(&settings.id, &settings.host, settings.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &topics.availability,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &settings.username {
+        options.set_credentials(username, settings.password.clone().unwrap_or_default());
+    }
+
+    info!(
+        "Connecting to MQTT broker at {}:{}",
+        settings.host, settings.port
+    );
+    let (client, connection) = Client::new(options, CAPACITY);
+
+    // The connection only passes the commands on so it never waits for the broker. Everything is
+    // published on a separate thread.
+    let (connected, announce) = mpsc::channel();
+    let command_topics = Topics::new(settings);
+    let command_control = control.clone();
+    thread::spawn(move || receive(connection, &command_topics, &command_control, connected));
+
+    let settings = settings.clone();
+    thread::spawn(move || publish(client, &settings, &topics, &control, announce));
+    Ok(())
+}
+
+/// Keep the connection to the broker alive and handle incoming messages. Every (re)connect is
+/// reported so rustylight can be announced again.
+fn receive(mut connection: Connection, topics: &Topics, control: &Control, connected: Sender<()>) {
+    for event in connection.iter() {
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker");
+                if connected.send(()).is_err() {
+                    return;
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                debug!("MQTT message on {}", message.topic);
+                if message.topic == topics.command {
+                    if let Err(e) = handle(&message.payload, control) {
+                        warn!("Invalid MQTT command: {:#}", e);
+                    }
+                } else if message.topic == topics.status
+                    && message.payload.as_ref() == b"online"
+                    && connected.send(()).is_err()
+                {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(
+                    "MQTT connection failed: {}. Retrying in {:?}",
+                    e, RECONNECT_DELAY
+                );
+                sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Publish the discovery config after every connect and the state whenever it changes
+fn publish(
+    client: Client,
+    settings: &MqttSettings,
+    topics: &Topics,
+    control: &Control,
+    connected: Receiver<()>,
+) {
+    // Nothing is published before the first connect
+    if connected.recv().is_err() {
+        return;
+    }
+    let mut announce = true;
+    let mut published = Value::Null;
+
+    loop {
+        if announce {
+            if let Err(e) = send_discovery(&client, settings, topics) {
+                warn!("Could not announce rustylight to Home Assistant: {}", e);
+            }
+            published = Value::Null;
+        }
+
+        let state = state_message(&control.state());
+        if state != published {
+            debug!("Publishing state {}", state);
+            let payload = state.to_string();
+            if let Err(e) = client.publish(&topics.state, QoS::AtLeastOnce, true, payload) {
+                warn!("Could not publish state: {}", e);
+            }
+            published = state;
+        }
+
+        announce = match connected.recv_timeout(STATE_INTERVAL) {
+            Ok(()) => true,
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+    }
+}
+
+fn send_discovery(client: &Client, settings: &MqttSettings, topics: &Topics) -> Result<()> {
+    let effect_list = effects::names();
+
+    let config = json!({
+        "name": null,
+        "unique_id": format!("{}_light", settings.id),
+        "schema": "json",
+        "command_topic": topics.command,
+        "state_topic": topics.state,
+        "availability_topic": topics.availability,
+        "brightness": true,
+        "brightness_scale": 255,
+        "supported_color_modes": ["rgb"],
+        "effect": true,
+        "effect_list": effect_list,
+        "device": {
+            "identifiers": [settings.id],
+            "name": settings.name,
+            "model": "Ambilight",
+            "manufacturer": "Rustylight",
+        },
+    });
+
+    client.publish(
+        &topics.discovery,
+        QoS::AtLeastOnce,
+        true,
+        config.to_string(),
+    )?;
+    client.subscribe(&topics.command, QoS::AtLeastOnce)?;
+    client.subscribe(&topics.status, QoS::AtLeastOnce)?;
+    client.publish(&topics.availability, QoS::AtLeastOnce, true, "online")?;
+    Ok(())
+}
+
+/// Pass a command of Home Assistant to the main loop
+fn handle(payload: &[u8], control: &Control) -> Result<()> {
+    let command: LightCommand = serde_json::from_slice(payload)?;
+    let current = control.state().effect;
+
+    let mut effect = match &command.effect {
+        Some(name) => Some(parse_effect(name, current.as_ref())?),
+        None => None,
+    };
+    if let Some(Rgb { r, g, b }) = command.color {
+        // The color is used by the chosen effect or shown on its own
+        let mut colored = effect
+            .unwrap_or(current)
+            .unwrap_or_else(|| EffectSettings::new(EffectKind::StaticColor));
+        colored.colors = vec![[r, g, b]];
+        effect = Some(Some(colored));
+    }
+
+    match command.state.as_deref() {
+        Some("ON") => control.send(Command::SetEnabled(true))?,
+        Some("OFF") => control.send(Command::SetEnabled(false))?,
+        Some(state) => return Err(anyhow!("Unknown state {}", state)),
+        None => {}
+    }
+    if let Some(brightness) = command.brightness {
+        control.send(Command::SetBrightness(brightness as f32 / 255.0))?;
+    }
+    if let Some(effect) = effect {
+        control.send(Command::SetEffect(effect))?;
+    }
+    Ok(())
+}
+
+/// The effect with the given name. The colors of the current effect are kept.
+fn parse_effect(name: &str, current: Option<&EffectSettings>) -> Result<Option<EffectSettings>> {
+    let mut effect = effects::by_name(name)?;
+    if let (Some(effect), Some(current)) = (&mut effect, current) {
+        effect.colors = current.colors.clone();
+    }
+    Ok(effect)
+}
+
+fn state_message(state: &State) -> Value {
+    let summary = Summary::new(state);
+    let [r, g, b] = summary.color;
+
+    json!({
+        "state": if summary.enabled { "ON" } else { "OFF" },
+        "brightness": (summary.brightness * 255.0).round() as u8,
+        "color_mode": "rgb",
+        "color": { "r": r, "g": g, "b": b },
+        "effect": summary.effect,
+    })
+}
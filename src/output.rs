@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use smart_leds::{SmartLedsWrite, RGB16, RGB8};
+use tracing::{debug, info};
+use ws281x_rpi::Ws2812Rpi;
+
+use crate::color;
+use crate::color_calibration::ColorCalibration;
+use crate::dithering::Ditherer;
+use crate::effects::{self, Layout, RunningEffect};
+use crate::lut::Lut3d;
+use crate::power::PowerLimit;
+use crate::settings::{ColorCalibrationSettings, EffectSettings, Settings};
+use crate::smoothing::Smoother;
+
+/// Anything the LED colors can be written to
+pub trait LedSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<()>;
+}
+
+/// The capture counts as inactive if no frame arrived for this long
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// WS281x lightstrip connected to the GPIO of a Raspberry Pi
+pub struct Ws2812Sink {
+    ws: Ws2812Rpi,
+}
+
+impl Ws2812Sink {
+    pub fn new(led_count: i32, pin: i32) -> Result<Self> {
+        let ws = Ws2812Rpi::new(led_count, pin)?;
+        Ok(Ws2812Sink { ws })
+    }
+}
+
+impl LedSink for Ws2812Sink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<()> {
+        self.ws
+            .write(leds.iter().cloned())
+            .map_err(|e| anyhow!("Could not write to the lightstrip: {:?}", e))
+    }
+}
+
+/// Shows the LEDs as a row of colored blocks in the terminal. The row is redrawn in place.
+pub struct TerminalSink;
+
+impl LedSink for TerminalSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<()> {
+        let mut line = String::from("\r");
+        for led in leds {
+            line.push_str(&format!("\x1b[48;2;{};{};{}m ", led.r, led.g, led.b));
+        }
+        line.push_str("\x1b[0m");
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(line.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Messages that can be sent to the output thread
+pub enum OutputMessage {
+    /// Newly captured LED colors
+    Frame(Vec<RGB16>),
+    /// The latest frame is a hard cut and shall be shown without smoothing
+    SceneCut,
+    /// Replace the color calibration at runtime
+    Calibration(ColorCalibrationSettings),
+    /// Fade the LEDs out (false) or back in (true)
+    Visible(bool),
+    /// Turn the LEDs on or off. While they are off no effect is shown and the capture fades out.
+    Enabled(bool),
+    /// Run an effect. It replaces any effect with the same priority and is shown as long as no
+    /// source with a higher priority is active.
+    StartEffect {
+        settings: EffectSettings,
+        priority: u8,
+    },
+    /// Stop the effect with the given priority
+    StopEffect { priority: u8 },
+}
+
+/// Messages of the output channel. Reconfiguration is only possible through Output::reconfigure.
+enum Message {
+    Output(OutputMessage),
+    Reconfigure {
+        pipeline: Box<Pipeline>,
+        period: Duration,
+    },
+    /// Fade everything out, turn the LEDs off and stop the thread
+    Shutdown,
+}
+
+/// Handle to the output thread. The output thread smooths and calibrates the captured colors or
+/// renders an effect, applies the LUT and the power limit and writes them to the sink at its own
+/// update rate, independent of the capture frame rate.
+pub struct Output {
+    sender: Sender<Message>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Output {
+    /// Start the output thread. The sink is created inside the thread because the lightstrip
+    /// driver can not be moved between threads.
+    pub fn spawn<F>(settings: &Settings, create_sink: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Box<dyn LedSink>> + Send + 'static,
+    {
+        // Set up the pipeline before starting the thread so errors are reported right away
+        let pipeline = Pipeline::new(settings)?;
+        let period = Self::period(settings);
+
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || Self::run(pipeline, period, create_sink()?, receiver));
+
+        Ok(Output {
+            sender,
+            handle: Some(handle),
+        })
+    }
+
+    /// Check that an output can be set up with the settings without starting it
+    pub fn check(settings: &Settings) -> Result<()> {
+        Pipeline::new(settings)?;
+        Ok(())
+    }
+
+    /// Send a message to the output thread. If the thread has stopped its error is returned.
+    pub fn send(&mut self, message: OutputMessage) -> Result<()> {
+        self.send_message(Message::Output(message))
+    }
+
+    /// Apply new settings without recreating the sink. Running effects and the fade state are
+    /// kept. The amount of LEDs can only be changed by spawning a new output.
+    pub fn reconfigure(&mut self, settings: &Settings) -> Result<()> {
+        let pipeline = Pipeline::new(settings)?;
+        self.send_message(Message::Reconfigure {
+            pipeline: Box::new(pipeline),
+            period: Self::period(settings),
+        })
+    }
+
+    /// Fade the LEDs out, write black and stop the output thread. Returns once the LEDs are off.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.send_message(Message::Shutdown)?;
+        self.stop()
+    }
+
+    /// Stop the output thread and wait until the sink has been released
+    pub fn stop(&mut self) -> Result<()> {
+        // Closing the channel stops the thread
+        let (closed, _) = mpsc::channel();
+        self.sender = closed;
+
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(anyhow!("Output thread panicked")),
+            None => Ok(()),
+        }
+    }
+
+    fn send_message(&mut self, message: Message) -> Result<()> {
+        if self.sender.send(message).is_ok() {
+            return Ok(());
+        }
+
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(Err(e))) => Err(e),
+            Some(Err(_)) => Err(anyhow!("Output thread panicked")),
+            _ => Err(anyhow!("Output thread has stopped")),
+        }
+    }
+
+    fn period(settings: &Settings) -> Duration {
+        Duration::from_secs_f64(1.0 / settings.smoothing.update_rate.max(1.0))
+    }
+
+    fn run(
+        mut pipeline: Pipeline,
+        mut period: Duration,
+        mut sink: Box<dyn LedSink>,
+        receiver: Receiver<Message>,
+    ) -> Result<()> {
+        info!("Updating LEDs every {:?}", period);
+
+        loop {
+            let tick = Instant::now();
+
+            // Take all messages that arrived since the last update
+            loop {
+                match receiver.try_recv() {
+                    Ok(Message::Output(message)) => pipeline.handle(message, tick),
+                    Ok(Message::Reconfigure {
+                        pipeline: mut replacement,
+                        period: replacement_period,
+                    }) => {
+                        debug!("Reconfiguring output");
+                        replacement.inherit(pipeline);
+                        pipeline = *replacement;
+                        period = replacement_period;
+                    }
+                    Ok(Message::Shutdown) => pipeline.shut_down(),
+                    Err(TryRecvError::Empty) => break,
+                    // The fade out is finished before stopping
+                    Err(TryRecvError::Disconnected) if pipeline.shutdown_fader.is_some() => break,
+                    Err(TryRecvError::Disconnected) => {
+                        debug!("Output channel closed. Stopping output thread");
+                        return Ok(());
+                    }
+                }
+            }
+
+            sink.write(&pipeline.render(tick))?;
+
+            if pipeline.is_shut_down() {
+                sink.write(&vec![RGB8::default(); pipeline.led_count])?;
+                info!("Turned the LEDs off");
+                return Ok(());
+            }
+
+            // Sleep for the rest of the period
+            if let Some(remaining) = period.checked_sub(tick.elapsed()) {
+                sleep(remaining);
+            }
+        }
+    }
+}
+
+/// Processing stages of the output thread. All stages work with 16 bit colors which are dithered
+/// or rounded to 8 bit right before the sink.
+struct Pipeline {
+    led_count: usize,
+    layout: Layout,
+    smoother: Smoother,
+    fader: Fader,
+    last_frame: Option<Instant>,
+    enabled: bool,
+    /// Fades out everything that is shown, not only the capture. Set once the output shuts down.
+    shutdown_fader: Option<Fader>,
+    /// Running effects by priority
+    effects: BTreeMap<u8, RunningEffect>,
+    last_render: Option<Instant>,
+    calibration: ColorCalibration,
+    lut: Option<Lut3d>,
+    power: PowerLimit,
+    ditherer: Option<Ditherer>,
+}
+
+impl Pipeline {
+    fn new(settings: &Settings) -> Result<Self> {
+        let led_count = settings.led_count as usize;
+        debug!("Setting up output with smoothing {:?}", settings.smoothing);
+
+        let lut = match &settings.lut {
+            Some(lut) => Some(Lut3d::load(
+                &Settings::resolve_path(&lut.path),
+                lut.interpolation,
+            )?),
+            None => None,
+        };
+
+        let layout = Layout::new(settings);
+        let mut effects = BTreeMap::new();
+        if let Some(effect) = &settings.idle_effect {
+            debug!("Using idle effect {:?}", effect);
+            effects.insert(
+                effects::IDLE_PRIORITY,
+                RunningEffect::new(effect.clone(), &layout),
+            );
+        }
+
+        Ok(Pipeline {
+            led_count,
+            layout,
+            smoother: Smoother::new(settings.smoothing, led_count),
+            fader: Fader::new(Duration::from_millis(settings.auto_off.fade_out_ms)),
+            last_frame: None,
+            enabled: true,
+            shutdown_fader: None,
+            effects,
+            last_render: None,
+            calibration: ColorCalibration::new(settings.color_calibration),
+            lut,
+            power: PowerLimit::new(settings.power),
+            ditherer: settings.dithering.then(|| Ditherer::new(led_count)),
+        })
+    }
+
+    fn handle(&mut self, message: OutputMessage, now: Instant) {
+        match message {
+            OutputMessage::Frame(leds) => {
+                self.smoother.set_target(&leds, now);
+                self.last_frame = Some(now);
+            }
+            OutputMessage::SceneCut => self.smoother.snap(),
+            OutputMessage::Calibration(settings) => {
+                debug!("Changing color calibration to {:?}", settings);
+                self.calibration = ColorCalibration::new(settings);
+            }
+            OutputMessage::Visible(visible) => self.fader.set_visible(visible),
+            OutputMessage::Enabled(enabled) => {
+                info!("Turning LEDs {}", if enabled { "on" } else { "off" });
+                self.enabled = enabled;
+            }
+            OutputMessage::StartEffect { settings, priority } => {
+                info!(
+                    "Starting effect {:?} with priority {}",
+                    settings.effect, priority
+                );
+                self.effects
+                    .insert(priority, RunningEffect::new(settings, &self.layout));
+            }
+            OutputMessage::StopEffect { priority } => {
+                if self.effects.remove(&priority).is_some() {
+                    info!("Stopped effect with priority {}", priority);
+                }
+            }
+        }
+    }
+
+    /// Take over the runtime state of the pipeline that is replaced. Effects that have been
+    /// started at runtime keep running, the idle effect comes from the new settings.
+    fn inherit(&mut self, previous: Pipeline) {
+        self.fader.visible = previous.fader.visible;
+        self.fader.level = previous.fader.level;
+        self.last_frame = previous.last_frame;
+        self.enabled = previous.enabled;
+
+        for (priority, effect) in previous.effects {
+            if priority != effects::IDLE_PRIORITY {
+                let restarted = RunningEffect::new(effect.settings, &self.layout);
+                self.effects.insert(priority, restarted);
+            }
+        }
+    }
+
+    /// Fade out whatever is shown, including effects
+    fn shut_down(&mut self) {
+        info!("Fading out the LEDs");
+        let mut fader = Fader::new(self.fader.duration);
+        fader.set_visible(false);
+        self.shutdown_fader = Some(fader);
+    }
+
+    /// Whether the LEDs have been faded out completely after shut_down
+    fn is_shut_down(&self) -> bool {
+        self.shutdown_fader
+            .as_ref()
+            .is_some_and(|fader| fader.is_hidden())
+    }
+
+    /// The capture is active while frames arrive and the LEDs are not faded out
+    fn is_capture_active(&self, now: Instant) -> bool {
+        let receiving = self
+            .last_frame
+            .is_some_and(|last| now.saturating_duration_since(last) < CAPTURE_TIMEOUT);
+        receiving && !self.fader.is_hidden()
+    }
+
+    /// Calculate the colors that shall be written to the sink at the given time. The source with
+    /// the highest priority is shown. Without any active source the LEDs are black.
+    fn render(&mut self, now: Instant) -> Vec<RGB8> {
+        let elapsed = self
+            .last_render
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_render = Some(now);
+
+        let capture_active = self.is_capture_active(now);
+        let effect = self.effects.iter_mut().next_back().filter(|(priority, _)| {
+            self.enabled && (!capture_active || **priority > effects::CAPTURE_PRIORITY)
+        });
+
+        let mut leds = match effect {
+            Some((_, effect)) => effect.render(elapsed, self.led_count),
+            None if capture_active => {
+                let mut leds = self.smoother.update(now);
+                self.fader.apply(&mut leds, now);
+                leds
+            }
+            None => vec![RGB16::default(); self.led_count],
+        };
+        if let Some(fader) = &mut self.shutdown_fader {
+            fader.apply(&mut leds, now);
+        }
+
+        self.calibration.apply(&mut leds);
+        if let Some(lut) = &self.lut {
+            lut.apply(&mut leds);
+        }
+        self.power.apply(&mut leds);
+
+        match &mut self.ditherer {
+            Some(ditherer) => ditherer.dither(&leds),
+            None => leds.iter().map(|led| color::to_rgb8(*led)).collect(),
+        }
+    }
+}
+
+/// Fades the LEDs out and back in
+struct Fader {
+    duration: Duration,
+    /// Brightness factor between 0 and 1
+    level: f32,
+    visible: bool,
+    last_update: Option<Instant>,
+}
+
+impl Fader {
+    fn new(duration: Duration) -> Self {
+        Fader {
+            duration,
+            level: 1.0,
+            visible: true,
+            last_update: None,
+        }
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        if visible == self.visible {
+            return;
+        }
+        debug!("Fading LEDs {}", if visible { "in" } else { "out" });
+        self.visible = visible;
+        // The fader is not applied while the LEDs are hidden, so the fade starts from now
+        self.last_update = None;
+    }
+
+    /// Whether the LEDs are completely faded out
+    fn is_hidden(&self) -> bool {
+        !self.visible && self.level <= 0.0
+    }
+
+    /// Move the level towards the target and scale the LEDs
+    fn apply(&mut self, leds: &mut [RGB16], now: Instant) {
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+
+        let step = if self.duration.is_zero() {
+            1.0
+        } else {
+            elapsed.as_secs_f32() / self.duration.as_secs_f32()
+        };
+        let target = if self.visible { 1.0 } else { 0.0 };
+        self.level = if self.level < target {
+            (self.level + step).min(target)
+        } else {
+            (self.level - step).max(target)
+        };
+
+        if self.level >= 1.0 {
+            return;
+        }
+
+        let scale = |value: u16| (value as f32 * self.level) as u16;
+        for led in leds.iter_mut() {
+            *led = RGB16 {
+                r: scale(led.r),
+                g: scale(led.g),
+                b: scale(led.b),
+            };
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use opencv::{
+    core::{Mat, Point, Point2f, Scalar, Size, Vector, BORDER_CONSTANT, DECOMP_LU},
+    imgcodecs, imgproc,
+    prelude::*,
+};
+use tracing::{debug, info};
+
+use crate::settings::{CaptureQuad, Settings};
+
+/// Warps the area of the camera image that shows the screen to a rectangle. This is needed when
+/// the screen is filmed with a camera instead of being captured with an HDMI grabber. The regions
+/// of the TranslationEngine will then be applied to the warped frame.
+pub struct PerspectiveCorrection {
+    transform: Mat,
+    size: Size,
+}
+
+impl PerspectiveCorrection {
+    /// Create the transformation from the corners in the camera image to a rectangle with the
+    /// given size.
+    pub fn new(quad: &CaptureQuad, size: Size) -> Result<Self> {
+        debug!(
+            "Setting up perspective correction for {:?} to size {:?}",
+            quad, size
+        );
+        let source = Vector::<Point2f>::from_iter(quad.corners().map(|(x, y)| Point2f::new(x, y)));
+
+        let (width, height) = (size.width as f32, size.height as f32);
+        let target = Vector::<Point2f>::from_iter([
+            Point2f::new(0.0, 0.0),
+            Point2f::new(width, 0.0),
+            Point2f::new(width, height),
+            Point2f::new(0.0, height),
+        ]);
+
+        let transform = imgproc::get_perspective_transform(&source, &target, DECOMP_LU)?;
+        Ok(PerspectiveCorrection { transform, size })
+    }
+
+    /// Size of the warped frames
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Warps source and writes the result to target
+    pub fn apply(&self, source: &Mat, target: &mut Mat) -> Result<()> {
+        imgproc::warp_perspective(
+            source,
+            target,
+            &self.transform,
+            self.size,
+            imgproc::INTER_LINEAR,
+            BORDER_CONSTANT,
+            Scalar::all(0.0),
+        )?;
+        Ok(())
+    }
+}
+
+/// Calibration helper for the perspective correction. The snapshot is saved next to the settings
+/// file so the result can be checked later. The corners of the screen will be detected in the
+/// snapshot and saved to the settings file.
+///
+/// For the automatic detection the screen should show a bright, evenly lit picture (e.g. a white
+/// image). With the highgui feature the four corners are instead selected by clicking on them in
+/// the order top left, top right, bottom right, bottom left.
+pub fn calibrate(snapshot: &Mat, settings: &mut Settings) -> Result<CaptureQuad> {
+    let mut snapshot_path = Settings::path();
+    snapshot_path.set_file_name("snapshot.png");
+    imgcodecs::imwrite(
+        snapshot_path.to_str().unwrap_or("snapshot.png"),
+        snapshot,
+        &Vector::new(),
+    )?;
+    info!("Saved calibration snapshot to {:?}", snapshot_path);
+
+    #[cfg(feature = "highgui")]
+    let quad = select_corners(snapshot)?;
+    #[cfg(not(feature = "highgui"))]
+    let quad = detect_corners(snapshot)?;
+
+    info!("Detected screen corners: {:?}", quad);
+    settings.capture_quad = Some(quad);
+    settings.save()?;
+
+    Ok(quad)
+}
+
+/// Finds the largest quadrilateral in the snapshot. This should be the brightly lit screen.
+fn detect_corners(snapshot: &Mat) -> Result<CaptureQuad> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color(snapshot, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+
+    let mut blurred = Mat::default();
+    imgproc::gaussian_blur(
+        &gray,
+        &mut blurred,
+        Size::new(5, 5),
+        0.0,
+        0.0,
+        BORDER_CONSTANT,
+    )?;
+
+    // Otsu chooses the threshold between the bright screen and the darker surroundings
+    let mut binary = Mat::default();
+    imgproc::threshold(
+        &blurred,
+        &mut binary,
+        0.0,
+        255.0,
+        imgproc::THRESH_BINARY | imgproc::THRESH_OTSU,
+    )?;
+
+    let mut contours = Vector::<Vector<Point>>::new();
+    imgproc::find_contours(
+        &binary,
+        &mut contours,
+        imgproc::RETR_EXTERNAL,
+        imgproc::CHAIN_APPROX_SIMPLE,
+        Point::default(),
+    )?;
+
+    let mut best: Option<(f64, Vector<Point>)> = None;
+    for contour in contours.iter() {
+        let mut approx = Vector::<Point>::new();
+        let epsilon = 0.02 * imgproc::arc_length(&contour, true)?;
+        imgproc::approx_poly_dp(&contour, &mut approx, epsilon, true)?;
+
+        if approx.len() != 4 {
+            continue;
+        }
+
+        let area = imgproc::contour_area(&approx, false)?;
+        let is_larger = match &best {
+            Some((best_area, _)) => area > *best_area,
+            None => true,
+        };
+        if is_larger {
+            best = Some((area, approx));
+        }
+    }
+
+    let (_, corners) = best.ok_or_else(|| anyhow!("Could not find the screen in the snapshot"))?;
+    let points: Vec<(f32, f32)> = corners.iter().map(|p| (p.x as f32, p.y as f32)).collect();
+    Ok(order_corners(&points))
+}
+
+/// Lets the user click on the four corners of the screen
+#[cfg(feature = "highgui")]
+fn select_corners(snapshot: &Mat) -> Result<CaptureQuad> {
+    use opencv::highgui;
+    use std::sync::{Arc, Mutex};
+
+    const WINDOW: &str = "calibration";
+
+    let clicks: Arc<Mutex<Vec<(f32, f32)>>> = Arc::new(Mutex::new(Vec::new()));
+    let callback_clicks = Arc::clone(&clicks);
+
+    highgui::named_window(WINDOW, highgui::WINDOW_NORMAL)?;
+    highgui::set_mouse_callback(
+        WINDOW,
+        Some(Box::new(move |event, x, y, _flags| {
+            if event == highgui::EVENT_LBUTTONDOWN {
+                let mut clicks = callback_clicks.lock().unwrap();
+                if clicks.len() < 4 {
+                    debug!("Selected corner at x: {}, y: {}", x, y);
+                    clicks.push((x as f32, y as f32));
+                }
+            }
+        })),
+    )?;
+
+    info!("Click on the corners of the screen: top left, top right, bottom right, bottom left");
+    loop {
+        let mut preview = snapshot.clone();
+        for (x, y) in clicks.lock().unwrap().iter() {
+            imgproc::circle(
+                &mut preview,
+                Point::new(*x as i32, *y as i32),
+                5,
+                Scalar::new(0.0, 0.0, 255.0, 0.0),
+                -1,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+        highgui::imshow(WINDOW, &preview)?;
+
+        let key = highgui::wait_key(30)?;
+        if key == 113 {
+            // quit with q
+            highgui::destroy_window(WINDOW)?;
+            return Err(anyhow!("Calibration aborted"));
+        }
+
+        if clicks.lock().unwrap().len() == 4 {
+            break;
+        }
+    }
+    highgui::destroy_window(WINDOW)?;
+
+    let clicks = clicks.lock().unwrap();
+    Ok(CaptureQuad {
+        top_left: clicks[0],
+        top_right: clicks[1],
+        bottom_right: clicks[2],
+        bottom_left: clicks[3],
+    })
+}
+
+/// Sorts four points into top left, top right, bottom right and bottom left. The top left corner
+/// has the smallest sum of its coordinates, the bottom right the largest. The top right corner has
+/// the largest difference between x and y, the bottom left the smallest.
+fn order_corners(points: &[(f32, f32)]) -> CaptureQuad {
+    let by = |key: fn(&(f32, f32)) -> f32, largest: bool| -> (f32, f32) {
+        let mut sorted = points.to_vec();
+        sorted.sort_by(|a, b| key(a).total_cmp(&key(b)));
+        if largest {
+            sorted[sorted.len() - 1]
+        } else {
+            sorted[0]
+        }
+    };
+
+    CaptureQuad {
+        top_left: by(|p| p.0 + p.1, false),
+        top_right: by(|p| p.0 - p.1, true),
+        bottom_right: by(|p| p.0 + p.1, true),
+        bottom_left: by(|p| p.0 - p.1, false),
+    }
+}
@@ -0,0 +1,79 @@
+use smart_leds::RGB16;
+use tracing::debug;
+
+use crate::color;
+use crate::settings::PowerSettings;
+
+/// Limits the brightness of every frame of the pipeline. The global maximum brightness is always
+/// applied. If a supply limit is configured and the estimated current of a frame would exceed it,
+/// the whole frame is scaled down so the colors stay the same. Works on the 16 bit colors so the
+/// dithering afterwards is not undone.
+pub struct PowerLimit {
+    settings: PowerSettings,
+    /// Whether the last frame had to be scaled down. Only used to log changes.
+    limiting: bool,
+}
+
+impl PowerLimit {
+    pub fn new(settings: PowerSettings) -> Self {
+        PowerLimit {
+            settings,
+            limiting: false,
+        }
+    }
+
+    pub fn apply(&mut self, leds: &mut [RGB16]) {
+        let max_brightness = self.settings.max_brightness.clamp(0.0, 1.0);
+        let channels = leds
+            .iter()
+            .map(|led| led.r as f32 + led.g as f32 + led.b as f32)
+            .sum::<f32>()
+            / color::MAX;
+        let supply = supply_scale(&self.settings, leds.len(), channels * max_brightness);
+
+        let limiting = supply < 1.0;
+        if limiting != self.limiting {
+            debug!(
+                "Power limit {} (estimated draw: {:.0} mA)",
+                if limiting { "reached" } else { "released" },
+                estimate_milliamps(&self.settings, leds.len(), channels)
+            );
+            self.limiting = limiting;
+        }
+
+        let scale = max_brightness * supply;
+        if scale >= 1.0 {
+            return;
+        }
+
+        // Round down so the limit is never exceeded
+        let channel = |value: u16| (value as f32 * scale) as u16;
+        for led in leds.iter_mut() {
+            led.r = channel(led.r);
+            led.g = channel(led.g);
+            led.b = channel(led.b);
+        }
+    }
+}
+
+/// Estimated current in mA that the lightstrip draws. `channels` is the sum of all color
+/// channels, with 1 being a channel at full brightness.
+fn estimate_milliamps(settings: &PowerSettings, led_count: usize, channels: f32) -> f32 {
+    led_count as f32 * settings.idle_milliamps + channels * settings.milliamps_per_channel
+}
+
+/// Factor by which the channels have to be scaled to stay within the supply limit
+fn supply_scale(settings: &PowerSettings, led_count: usize, channels: f32) -> f32 {
+    let Some(limit) = settings.supply_limit_amps else {
+        return 1.0;
+    };
+
+    let idle = led_count as f32 * settings.idle_milliamps;
+    let current = channels * settings.milliamps_per_channel;
+    let budget = (limit * 1000.0 - idle).max(0.0);
+    if current > budget {
+        budget / current
+    } else {
+        1.0
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use opencv::{
+    core::{Mat, Size, Vector},
+    imgcodecs, imgproc,
+    prelude::*,
+};
+use serde_json::{json, Value};
+use smart_leds::RGB8;
+use tungstenite::{Message, WebSocket};
+
+use crate::control::Control;
+use crate::effects::Layout;
+use crate::output::LedSink;
+use crate::settings::Settings;
+
+/// Latest LED colors and capture snapshot for the web preview. It can be cloned for every thread
+/// that provides or shows them.
+#[derive(Clone, Default)]
+pub struct Preview {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    leds: Vec<RGB8>,
+    /// JPEG encoded capture snapshot
+    snapshot: Option<Arc<Vec<u8>>>,
+    /// Increased with every snapshot so each viewer sends it only once
+    snapshot_id: u64,
+    /// Amount of connected viewers. Nothing is recorded without them.
+    viewers: usize,
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether anyone is looking at the preview
+    pub fn is_watched(&self) -> bool {
+        self.lock().viewers > 0
+    }
+
+    pub fn set_leds(&self, leds: &[RGB8]) {
+        let mut shared = self.lock();
+        if shared.viewers > 0 {
+            shared.leds.clear();
+            shared.leds.extend_from_slice(leds);
+        }
+    }
+
+    /// Downscale the frame and store it as JPEG
+    pub fn set_snapshot(&self, frame: &Mat, settings: &Settings) -> Result<()> {
+        let size = frame.size()?;
+        let width = settings.preview.snapshot_width.clamp(1, size.width.max(1));
+        let height = (size.height as f64 * width as f64 / size.width.max(1) as f64).round();
+
+        let mut small = Mat::default();
+        imgproc::resize(
+            frame,
+            &mut small,
+            Size::new(width, (height as i32).max(1)),
+            0.0,
+            0.0,
+            imgproc::INTER_AREA,
+        )?;
+
+        let mut jpeg = Vector::<u8>::new();
+        let params = Vector::from_slice(&[
+            imgcodecs::IMWRITE_JPEG_QUALITY,
+            settings.preview.jpeg_quality.clamp(0, 100),
+        ]);
+        imgcodecs::imencode(".jpg", &small, &mut jpeg, &params)?;
+
+        let mut shared = self.lock();
+        shared.snapshot = Some(Arc::new(jpeg.to_vec()));
+        shared.snapshot_id += 1;
+        Ok(())
+    }
+
+    /// Send the preview to a connected browser until it disconnects
+    pub fn stream<S: Read + Write>(
+        &self,
+        mut socket: WebSocket<S>,
+        control: &Control,
+    ) -> Result<()> {
+        let _viewer = Viewer::new(self.clone());
+        let mut layout = Value::Null;
+        let mut snapshot_id = 0;
+
+        loop {
+            let tick = Instant::now();
+            let settings = control.state().settings;
+
+            // The layout is only sent again if the settings changed it
+            let current = layout_message(&settings);
+            if current != layout {
+                socket.send(Message::text(current.to_string()))?;
+                layout = current;
+            }
+
+            let (leds, snapshot) = {
+                let shared = self.lock();
+                let snapshot = match &shared.snapshot {
+                    Some(jpeg) if shared.snapshot_id != snapshot_id => {
+                        snapshot_id = shared.snapshot_id;
+                        Some(Arc::clone(jpeg))
+                    }
+                    _ => None,
+                };
+                (shared.leds.clone(), snapshot)
+            };
+
+            let leds: Vec<[u8; 3]> = leds.iter().map(|led| [led.r, led.g, led.b]).collect();
+            let message = json!({ "type": "leds", "leds": leds });
+            socket.send(Message::text(message.to_string()))?;
+            if let Some(jpeg) = snapshot {
+                socket.send(Message::binary(jpeg.to_vec()))?;
+            }
+
+            let period = Duration::from_secs_f64(1.0 / settings.preview.fps.max(0.1));
+            if let Some(remaining) = period.checked_sub(tick.elapsed()) {
+                sleep(remaining);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Counts a viewer as long as it is connected
+struct Viewer(Preview);
+
+impl Viewer {
+    fn new(preview: Preview) -> Self {
+        preview.lock().viewers += 1;
+        Viewer(preview)
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        self.0.lock().viewers -= 1;
+    }
+}
+
+/// Where the LEDs are placed and which part of the picture they sample. Coordinates are relative
+/// to the size of the screen.
+fn layout_message(settings: &Settings) -> Value {
+    let layout = Layout::new(settings);
+    let (width, height) = (layout.width as i32, layout.height as i32);
+    let depths = settings.capture_area_size.resolve(width, height);
+
+    json!({
+        "type": "layout",
+        "width": width,
+        "height": height,
+        "start_corner": settings.start_corner,
+        "direction": settings.direction,
+        "leds": layout.positions,
+        "capture_area": {
+            "top": depths.top as f32 / layout.height,
+            "right": depths.right as f32 / layout.width,
+            "bottom": depths.bottom as f32 / layout.height,
+            "left": depths.left as f32 / layout.width,
+        },
+    })
+}
+
+/// Records the colors that are written to the lightstrip for the preview
+pub struct PreviewSink {
+    sink: Box<dyn LedSink>,
+    preview: Preview,
+}
+
+impl PreviewSink {
+    pub fn new(sink: Box<dyn LedSink>, preview: Preview) -> Self {
+        PreviewSink { sink, preview }
+    }
+}
+
+impl LedSink for PreviewSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<()> {
+        self.preview.set_leds(leds);
+        self.sink.write(leds)
+    }
+}
@@ -0,0 +1,164 @@
+use opencv::core::Vec3b;
+use smart_leds::RGB16;
+
+/// Resamples the border pixels to exactly `led_count` LEDs.
+///
+/// Every LED covers an equally sized segment of the border. Usually the number of border pixels
+/// is not a multiple of the number of LEDs, so a segment will start and end somewhere inside a
+/// pixel. Pixels that are only partially covered by a segment contribute to the mean of that LED
+/// proportionally to the covered area.
+///
+/// To avoid any drift along the strip all calculations are done in integer arithmetic: Both the
+/// pixels and the segments are scaled so that a pixel is `led_count` units long and a segment is
+/// `border.len()` units long.
+///
+/// The result has 16 bit precision, so the fraction of the mean is kept for the later stages.
+pub fn resample(border: &[Vec3b], led_count: usize) -> Vec<RGB16> {
+    let pixel_count = border.len();
+    if pixel_count == 0 || led_count == 0 {
+        return vec![RGB16::default(); led_count];
+    }
+
+    let mut leds: Vec<RGB16> = Vec::with_capacity(led_count);
+
+    for led in 0..led_count {
+        let (start, end) = segment(led, pixel_count);
+
+        // Weighted sums of the BGR values
+        let mut sum_b: u64 = 0;
+        let mut sum_g: u64 = 0;
+        let mut sum_r: u64 = 0;
+
+        for (pixel, weight) in coverage(start, end, led_count) {
+            let value = border[pixel];
+            sum_b += value[0] as u64 * weight;
+            sum_g += value[1] as u64 * weight;
+            sum_r += value[2] as u64 * weight;
+        }
+
+        // The weights of one segment always add up to its length. 8 bit values are scaled to 16
+        // bit by 257 (255 * 257 = 65535).
+        let total = (end - start) as u64;
+        let mean = |sum: u64| ((sum * 257 + total / 2) / total) as u16;
+        leds.push(RGB16 {
+            r: mean(sum_r),
+            g: mean(sum_g),
+            b: mean(sum_b),
+        });
+    }
+
+    leds
+}
+
+/// Start and end of the segment belonging to an LED in scaled units
+fn segment(led: usize, pixel_count: usize) -> (usize, usize) {
+    (led * pixel_count, (led + 1) * pixel_count)
+}
+
+/// Returns the index and the covered length of each pixel that lies in the segment [start, end)
+fn coverage(start: usize, end: usize, led_count: usize) -> impl Iterator<Item = (usize, u64)> {
+    let first = start / led_count;
+    let last = (end - 1) / led_count;
+
+    (first..=last).map(move |pixel| {
+        let pixel_start = pixel * led_count;
+        let pixel_end = pixel_start + led_count;
+        let covered = end.min(pixel_end) - start.max(pixel_start);
+        (pixel, covered as u64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Border with a single channel per pixel. The value ends up in the red channel of the LEDs.
+    fn border(values: &[u8]) -> Vec<Vec3b> {
+        values
+            .iter()
+            .map(|value| Vec3b::from_array([0, 0, *value]))
+            .collect()
+    }
+
+    #[test]
+    fn same_count_keeps_every_pixel() {
+        let values: Vec<u8> = (0..=255).collect();
+        let leds = resample(&border(&values), values.len());
+
+        assert_eq!(leds.len(), values.len());
+        for (led, value) in leds.iter().zip(&values) {
+            assert_eq!(led.r, *value as u16 * 257);
+        }
+    }
+
+    #[test]
+    fn every_pixel_is_shared_by_the_leds_covering_it() {
+        let (pixel_count, led_count) = (2236, 123);
+
+        for lit in 0..pixel_count {
+            let mut values = vec![0; pixel_count];
+            values[lit] = 255;
+            let leds = resample(&border(&values), led_count);
+            assert_eq!(leds.len(), led_count);
+
+            // LED i covers [i * pixel_count, (i + 1) * pixel_count) and the lit pixel covers
+            // [lit * led_count, (lit + 1) * led_count) in units of 1 / led_count pixels
+            let mut total = 0;
+            for (index, led) in leds.iter().enumerate() {
+                let (start, end) = (index * pixel_count, (index + 1) * pixel_count);
+                let (lit_start, lit_end) = (lit * led_count, (lit + 1) * led_count);
+                let covered = end.min(lit_end).saturating_sub(start.max(lit_start));
+
+                let expected =
+                    (covered as u64 * 65535 + pixel_count as u64 / 2) / pixel_count as u64;
+                assert_eq!(
+                    led.r as u64, expected,
+                    "LED {} with pixel {} lit",
+                    index, lit
+                );
+                total += covered;
+            }
+
+            // Nothing of the pixel is lost or counted twice
+            assert_eq!(total, led_count);
+        }
+    }
+
+    #[test]
+    fn remainder_does_not_drift() {
+        // 2236 = 18 * 123 + 22, so most segments start and end inside a pixel
+        let leds = resample(&border(&[200; 2236]), 123);
+        assert!(leds.iter().all(|led| led.r == 200 * 257));
+
+        // The first half of the border is black. LED 61 covers [1108.9, 1127.1) and is the only
+        // one that is half lit.
+        let values: Vec<u8> = (0..2236)
+            .map(|pixel| if pixel < 1118 { 0 } else { 255 })
+            .collect();
+        let leds = resample(&border(&values), 123);
+        for (index, led) in leds.iter().enumerate() {
+            let expected = match index {
+                0..=60 => 0,
+                61 => 32768,
+                _ => 65535,
+            };
+            assert_eq!(led.r, expected, "LED {}", index);
+        }
+    }
+
+    #[test]
+    fn fewer_pixels_than_leds() {
+        // 7 LEDs on 3 pixels: every LED is 3/7 of a pixel long
+        let leds = resample(&border(&[0, 70, 140]), 7);
+
+        let expected = [0, 0, 11993, 17990, 23987, 35980, 35980];
+        let red: Vec<u16> = leds.iter().map(|led| led.r).collect();
+        assert_eq!(red, expected);
+    }
+
+    #[test]
+    fn empty_border_is_black() {
+        let leds = resample(&[], 5);
+        assert_eq!(leds, vec![RGB16::default(); 5]);
+    }
+}
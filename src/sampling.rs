@@ -0,0 +1,208 @@
+use opencv::core::Vec3b;
+
+use crate::settings::SamplingMode;
+
+/// Amount of bits per channel that are used to sort the pixels into bins when searching for the
+/// dominant color. 3 bits give 8 levels per channel and 512 bins in total.
+const DOMINANT_BITS: u32 = 3;
+
+/// Reduces a column or row of pixels to a single color with the given sampling mode
+pub fn sample(mode: SamplingMode, pixels: &[Vec3b]) -> Vec3b {
+    if pixels.is_empty() {
+        return Vec3b::default();
+    }
+
+    match mode {
+        SamplingMode::Mean => mean(pixels),
+        SamplingMode::SquaredMean => squared_mean(pixels),
+        SamplingMode::Median => median(pixels),
+        SamplingMode::Dominant => dominant(pixels),
+        SamplingMode::MostSaturated => most_saturated(pixels),
+    }
+}
+
+/// Arithmetic mean of each channel
+fn mean(pixels: &[Vec3b]) -> Vec3b {
+    let mut sum = [0u32; 3];
+    for pixel in pixels {
+        for (channel, total) in sum.iter_mut().enumerate() {
+            *total += pixel[channel] as u32;
+        }
+    }
+
+    let count = pixels.len() as u32;
+    Vec3b::from_array(sum.map(|s| (s / count) as u8))
+}
+
+/// Root of the mean of the squared values of each channel. Bright pixels have a bigger influence
+/// which is closer to how the eye perceives the mixed color.
+fn squared_mean(pixels: &[Vec3b]) -> Vec3b {
+    let mut sum = [0u64; 3];
+    for pixel in pixels {
+        for (channel, total) in sum.iter_mut().enumerate() {
+            *total += (pixel[channel] as u64).pow(2);
+        }
+    }
+
+    let count = pixels.len() as f64;
+    Vec3b::from_array(sum.map(|s| (s as f64 / count).sqrt().round() as u8))
+}
+
+/// Median of each channel
+fn median(pixels: &[Vec3b]) -> Vec3b {
+    let mut values: Vec<u8> = Vec::with_capacity(pixels.len());
+    let mut result = [0u8; 3];
+
+    for (channel, value) in result.iter_mut().enumerate() {
+        values.clear();
+        values.extend(pixels.iter().map(|pixel| pixel[channel]));
+
+        let middle = values.len() / 2;
+        *value = *values.select_nth_unstable(middle).1;
+    }
+
+    Vec3b::from_array(result)
+}
+
+/// Sorts the pixels into coarse color bins and returns the mean of the bin that holds the most
+/// pixels
+fn dominant(pixels: &[Vec3b]) -> Vec3b {
+    let shift = 8 - DOMINANT_BITS;
+    let bin_of = |pixel: &Vec3b| -> usize {
+        (((pixel[0] >> shift) as usize) << (2 * DOMINANT_BITS))
+            | (((pixel[1] >> shift) as usize) << DOMINANT_BITS)
+            | (pixel[2] >> shift) as usize
+    };
+
+    let mut counts = vec![0u32; 1 << (3 * DOMINANT_BITS)];
+    for pixel in pixels {
+        counts[bin_of(pixel)] += 1;
+    }
+
+    // On a tie the first bin wins
+    let mut best_bin = 0;
+    for (bin, count) in counts.iter().enumerate() {
+        if *count > counts[best_bin] {
+            best_bin = bin;
+        }
+    }
+
+    let members: Vec<Vec3b> = pixels
+        .iter()
+        .filter(|pixel| bin_of(pixel) == best_bin)
+        .copied()
+        .collect();
+    mean(&members)
+}
+
+/// Mean of the quarter of pixels with the highest saturation. Using more than a single pixel keeps
+/// the result stable when the content moves.
+fn most_saturated(pixels: &[Vec3b]) -> Vec3b {
+    let saturation = |pixel: &Vec3b| -> u32 {
+        let max = pixel[0].max(pixel[1]).max(pixel[2]) as u32;
+        let min = pixel[0].min(pixel[1]).min(pixel[2]) as u32;
+        // Black has no saturation
+        ((max - min) * 255).checked_div(max).unwrap_or(0)
+    };
+
+    let mut sorted = pixels.to_vec();
+    sorted.sort_by_key(|pixel| std::cmp::Reverse(saturation(pixel)));
+
+    let count = (sorted.len() / 4).max(1);
+    mean(&sorted[..count])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [SamplingMode; 5] = [
+        SamplingMode::Mean,
+        SamplingMode::SquaredMean,
+        SamplingMode::Median,
+        SamplingMode::Dominant,
+        SamplingMode::MostSaturated,
+    ];
+
+    fn pixels(colors: &[[u8; 3]]) -> Vec<Vec3b> {
+        colors
+            .iter()
+            .map(|color| Vec3b::from_array(*color))
+            .collect()
+    }
+
+    #[test]
+    fn uniform_pixels_keep_their_color() {
+        let uniform = pixels(&[[10, 120, 230]; 7]);
+        for mode in MODES {
+            assert_eq!(sample(mode, &uniform), uniform[0], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn no_pixels_are_black() {
+        for mode in MODES {
+            assert_eq!(sample(mode, &[]), Vec3b::default(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn mean_averages_each_channel() {
+        let column = pixels(&[[0, 100, 255], [100, 200, 255], [200, 0, 0]]);
+        assert_eq!(
+            sample(SamplingMode::Mean, &column),
+            Vec3b::from_array([100, 100, 170])
+        );
+    }
+
+    #[test]
+    fn squared_mean_favours_bright_pixels() {
+        let column = pixels(&[[0, 0, 0], [200, 200, 200]]);
+        // sqrt(200^2 / 2) instead of 100
+        assert_eq!(
+            sample(SamplingMode::SquaredMean, &column),
+            Vec3b::from_array([141, 141, 141])
+        );
+    }
+
+    #[test]
+    fn median_ignores_outliers() {
+        let column = pixels(&[
+            [10, 10, 10],
+            [12, 12, 12],
+            [255, 255, 255],
+            [11, 11, 11],
+            [9, 9, 9],
+        ]);
+        assert_eq!(
+            sample(SamplingMode::Median, &column),
+            Vec3b::from_array([11, 11, 11])
+        );
+    }
+
+    #[test]
+    fn dominant_returns_the_most_common_color() {
+        let column = pixels(&[
+            [200, 10, 10],
+            [0, 0, 250],
+            [204, 14, 12],
+            [0, 250, 0],
+            [202, 12, 14],
+        ]);
+        assert_eq!(
+            sample(SamplingMode::Dominant, &column),
+            Vec3b::from_array([202, 12, 12])
+        );
+    }
+
+    #[test]
+    fn most_saturated_prefers_vivid_pixels() {
+        let mut column = pixels(&[[128, 128, 128]; 6]);
+        column.extend(pixels(&[[255, 0, 0], [250, 10, 0]]));
+        // The quarter of eight pixels are the two red ones
+        assert_eq!(
+            sample(SamplingMode::MostSaturated, &column),
+            Vec3b::from_array([252, 5, 0])
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use opencv::core::Vec3b;
+use tracing::debug;
+
+/// Amount of histogram bins per channel
+const BINS: usize = 16;
+
+/// Detects hard cuts by comparing the color histograms of the captured border of successive
+/// frames. Continuous motion changes the histogram only a little, while a cut replaces most of it.
+pub struct SceneCutDetector {
+    /// Histograms above this difference (0-1) are a cut
+    threshold: f32,
+    previous: Option<[[f32; BINS]; 3]>,
+}
+
+impl SceneCutDetector {
+    pub fn new(threshold: f32) -> Self {
+        SceneCutDetector {
+            threshold,
+            previous: None,
+        }
+    }
+
+    /// Returns true if the border differs from the one of the previous frame by a hard cut
+    pub fn update(&mut self, border: &[Vec3b]) -> bool {
+        let histogram = Self::histogram(border);
+
+        let is_cut = match &self.previous {
+            Some(previous) => {
+                let difference = Self::difference(previous, &histogram);
+                if difference > self.threshold {
+                    debug!(
+                        "Scene cut detected (histogram difference: {:.2})",
+                        difference
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        };
+
+        self.previous = Some(histogram);
+        is_cut
+    }
+
+    /// Normalized histogram of each channel
+    fn histogram(border: &[Vec3b]) -> [[f32; BINS]; 3] {
+        let mut histogram = [[0.0; BINS]; 3];
+        if border.is_empty() {
+            return histogram;
+        }
+
+        let weight = 1.0 / border.len() as f32;
+        for pixel in border {
+            for (channel, bins) in histogram.iter_mut().enumerate() {
+                bins[pixel[channel] as usize * BINS / 256] += weight;
+            }
+        }
+        histogram
+    }
+
+    /// Difference between two histograms between 0 (equal) and 1 (no overlap)
+    fn difference(a: &[[f32; BINS]; 3], b: &[[f32; BINS]; 3]) -> f32 {
+        let total: f32 = a
+            .iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+
+        // Each histogram sums up to 1 per channel, so the difference of a channel is at most 2
+        total / 6.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn border(colors: &[[u8; 3]]) -> Vec<Vec3b> {
+        colors
+            .iter()
+            .cycle()
+            .take(120)
+            .map(|color| Vec3b::from_array(*color))
+            .collect()
+    }
+
+    #[test]
+    fn first_frame_is_no_cut() {
+        let mut detector = SceneCutDetector::new(0.5);
+        assert!(!detector.update(&border(&[[255, 255, 255]])));
+    }
+
+    #[test]
+    fn large_difference_is_a_cut() {
+        let mut detector = SceneCutDetector::new(0.5);
+        detector.update(&border(&[[20, 40, 200], [30, 50, 180]]));
+        assert!(detector.update(&border(&[[230, 200, 10], [250, 180, 0]])));
+    }
+
+    #[test]
+    fn small_difference_is_no_cut() {
+        let mut detector = SceneCutDetector::new(0.5);
+        detector.update(&border(&[[20, 40, 200], [30, 50, 180]]));
+        // Half of the pixels change slightly, some of them into the neighbouring bin
+        assert!(!detector.update(&border(&[[20, 40, 200], [34, 54, 184]])));
+        assert!(!detector.update(&border(&[[20, 40, 200], [34, 54, 184]])));
+    }
+
+    #[test]
+    fn identical_frames_have_no_difference() {
+        let histogram = SceneCutDetector::histogram(&border(&[[1, 2, 3], [200, 100, 50]]));
+        assert_eq!(SceneCutDetector::difference(&histogram, &histogram), 0.0);
+    }
+
+    #[test]
+    fn disjoint_frames_have_the_largest_difference() {
+        let black = SceneCutDetector::histogram(&border(&[[0, 0, 0]]));
+        let white = SceneCutDetector::histogram(&border(&[[255, 255, 255]]));
+        assert!((SceneCutDetector::difference(&black, &white) - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,6 +1,11 @@
-use std::{env, fs, path::PathBuf};
+use std::{
+    env, fs,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use tracing::level_filters::LevelFilter;
 
@@ -19,7 +24,7 @@ pub enum StartCorner {
 }
 
 /// Compatibility loglevel enum because LevelFilter does not implement Serialize/Deserialize
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, ValueEnum)]
 pub enum LogLevel {
     Info,
     Debug,
@@ -41,7 +46,7 @@ impl From<LogLevel> for LevelFilter {
 /// to process each frame in 1080p or more if only 100-200 pixels are needed for the lightstrip.
 /// This also reduces load on the system running rustylight. This is useful because usually low
 /// powered devices will be used for a diy ambilight setup.
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Resolution {
     FHD,
     HD,
@@ -59,30 +64,721 @@ impl From<Resolution> for (f64, f64) {
     }
 }
 
+/// Unit in which the capture depth of the edges is given
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum DepthUnit {
+    /// Absolute amount of pixels in the processed frame
+    Pixels,
+    /// Percentage of the frame. Top and bottom are relative to the frame height, left and right
+    /// are relative to the frame width.
+    Percent,
+}
+
+/// How deep into the picture the LEDs of each edge will sample
+///
+/// For compatibility a single number is still accepted. It will be used as the depth in pixels
+/// for all four edges.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CaptureAreaSize {
+    Uniform(i32),
+    PerEdge {
+        unit: DepthUnit,
+        top: f64,
+        right: f64,
+        bottom: f64,
+        left: f64,
+    },
+}
+
+/// Capture depth of each edge in pixels of the processed frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EdgeDepths {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+impl CaptureAreaSize {
+    /// Converts the configured depths to pixels for a frame of the given size. Every edge is at
+    /// least one pixel deep.
+    pub fn resolve(&self, width: i32, height: i32) -> EdgeDepths {
+        match *self {
+            CaptureAreaSize::Uniform(depth) => EdgeDepths {
+                top: depth.max(1),
+                right: depth.max(1),
+                bottom: depth.max(1),
+                left: depth.max(1),
+            },
+            CaptureAreaSize::PerEdge {
+                unit,
+                top,
+                right,
+                bottom,
+                left,
+            } => {
+                let to_pixels = |value: f64, length: i32| -> i32 {
+                    let pixels = match unit {
+                        DepthUnit::Pixels => value,
+                        DepthUnit::Percent => value / 100.0 * length as f64,
+                    };
+                    (pixels.round() as i32).max(1)
+                };
+
+                EdgeDepths {
+                    top: to_pixels(top, height),
+                    right: to_pixels(right, width),
+                    bottom: to_pixels(bottom, height),
+                    left: to_pixels(left, width),
+                }
+            }
+        }
+    }
+}
+
+impl EdgeDepths {
+    /// Opposing edges must not overlap
+    pub fn fits(&self, width: i32, height: i32) -> bool {
+        self.left + self.right < width && self.top + self.bottom < height
+    }
+
+    /// Amount of pixels along the border of a frame with the given size. Every pixel of the
+    /// captured area belongs to exactly one edge.
+    pub fn border_length(&self, width: i32, height: i32) -> i32 {
+        2 * width + 2 * height - self.top - self.right - self.bottom - self.left
+    }
+}
+
+/// Corners of the screen in the camera image. They are used to correct the perspective when the
+/// screen is filmed with a camera instead of being captured with an HDMI grabber.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct CaptureQuad {
+    pub top_left: (f32, f32),
+    pub top_right: (f32, f32),
+    pub bottom_right: (f32, f32),
+    pub bottom_left: (f32, f32),
+}
+
+impl CaptureQuad {
+    /// Corners in clockwise order starting from the top left
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        [
+            self.top_left,
+            self.top_right,
+            self.bottom_right,
+            self.bottom_left,
+        ]
+    }
+}
+
+/// Algorithm that reduces a column or row of the captured area to a single color
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum SamplingMode {
+    /// Arithmetic mean. Busy content tends to wash out to grey.
+    #[default]
+    Mean,
+    /// Root of the mean of the squared values. Closer to the perceived color.
+    SquaredMean,
+    /// Median of each channel
+    Median,
+    /// Most common color using histogram binning
+    Dominant,
+    /// Mean of the most saturated pixels
+    MostSaturated,
+}
+
+/// Sampling mode of each edge
+///
+/// A single mode is used for all four edges.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SamplingModes {
+    Uniform(SamplingMode),
+    PerEdge(EdgeSamplingModes),
+}
+
+impl Default for SamplingModes {
+    fn default() -> Self {
+        SamplingModes::Uniform(SamplingMode::default())
+    }
+}
+
+impl SamplingModes {
+    pub fn resolve(&self) -> EdgeSamplingModes {
+        match *self {
+            SamplingModes::Uniform(mode) => EdgeSamplingModes {
+                top: mode,
+                right: mode,
+                bottom: mode,
+                left: mode,
+            },
+            SamplingModes::PerEdge(modes) => modes,
+        }
+    }
+}
+
+/// Sampling mode of the LEDs along each edge
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct EdgeSamplingModes {
+    pub top: SamplingMode,
+    pub right: SamplingMode,
+    pub bottom: SamplingMode,
+    pub left: SamplingMode,
+}
+
+/// Algorithm that smooths the LED colors over time
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum SmoothingAlgorithm {
+    /// Colors are shown as soon as they have been captured
+    #[default]
+    Off,
+    /// Fades linearly from the current colors to a new frame over the time window
+    Linear,
+    /// Exponential moving average with the time window as time constant
+    Ema,
+    /// Weighted average of all frames within the time window. Older frames have less weight.
+    Decay,
+}
+
+/// Temporal smoothing between the captured colors and the LED output
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SmoothingSettings {
+    pub algorithm: SmoothingAlgorithm,
+    /// Time window of the smoothing in milliseconds
+    pub time_window_ms: u64,
+    /// Rate in Hz at which the LEDs are updated. This is independent of the capture frame rate.
+    pub update_rate: f64,
+    /// Exponent of the weights of the decay smoothing. Higher values favour recent frames.
+    pub decay: f64,
+    /// Histogram difference of the border (0-1) above which a frame is a hard cut. Cuts bypass
+    /// the smoothing. No detection if not set.
+    pub scene_cut_threshold: Option<f32>,
+}
+
+impl Default for SmoothingSettings {
+    fn default() -> Self {
+        SmoothingSettings {
+            algorithm: SmoothingAlgorithm::Off,
+            time_window_ms: 200,
+            update_rate: 60.0,
+            decay: 1.0,
+            scene_cut_threshold: Some(0.5),
+        }
+    }
+}
+
+/// Adjusts the captured colors to the lightstrip. Per channel values are in the order red, green,
+/// blue.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ColorCalibrationSettings {
+    /// Gamma of each channel. Values above 1 darken the mid tones.
+    pub gamma: [f32; 3],
+    /// Gain of each channel. Used to set the white point of the strip.
+    pub gain: [f32; 3],
+    /// Captured values up to the black level will turn the LEDs off
+    pub black_level: u8,
+    /// Overall brightness factor
+    pub brightness: f32,
+    /// Saturation factor. 0 gives grey, 1 keeps the captured saturation.
+    pub saturation: f32,
+}
+
+impl Default for ColorCalibrationSettings {
+    fn default() -> Self {
+        ColorCalibrationSettings {
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            black_level: 0,
+            brightness: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+/// Interpolation between the grid points of a 3D LUT
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum LutInterpolation {
+    Trilinear,
+    #[default]
+    Tetrahedral,
+}
+
+/// 3D LUT in .cube format that is applied to every LED color
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LutSettings {
+    /// Path to the .cube file. Relative paths are relative to the settings file.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub interpolation: LutInterpolation,
+}
+
+/// Tone mapping for HDR10 content that is delivered as flat, desaturated SDR by the grabber. The
+/// captured values are decoded as BT.2020 PQ, tone mapped and converted to BT.709.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ToneMappingSettings {
+    /// Reference white in nits. Brightness is expressed relative to it before tone mapping.
+    pub white_nits: f32,
+    /// Peak brightness of the content in nits. Brighter values are clipped.
+    pub peak_nits: f32,
+    /// Saturation factor applied after the conversion
+    pub saturation: f32,
+    /// Contrast factor applied after the conversion
+    pub contrast: f32,
+}
+
+impl Default for ToneMappingSettings {
+    fn default() -> Self {
+        ToneMappingSettings {
+            white_nits: 203.0,
+            peak_nits: 1000.0,
+            saturation: 1.2,
+            contrast: 1.1,
+        }
+    }
+}
+
+/// Power model of the lightstrip. If the estimated current would exceed the supply limit, the
+/// whole frame is scaled down.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct PowerSettings {
+    /// Current in mA that a single color channel of one LED draws at full brightness
+    pub milliamps_per_channel: f32,
+    /// Current in mA that each LED draws even when it is off
+    pub idle_milliamps: f32,
+    /// Current in A the power supply can deliver. No limit if not set.
+    pub supply_limit_amps: Option<f32>,
+    /// Global maximum brightness between 0 and 1
+    pub max_brightness: f32,
+}
+
+impl Default for PowerSettings {
+    fn default() -> Self {
+        PowerSettings {
+            milliamps_per_channel: 20.0,
+            idle_milliamps: 1.0,
+            supply_limit_amps: None,
+            max_brightness: 1.0,
+        }
+    }
+}
+
+/// Turns the LEDs off when the source shows no signal or the picture does not change anymore
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoOffSettings {
+    /// Off by default because dark or uniform scenes would turn the LEDs off as well
+    pub enabled: bool,
+    /// Frames darker than this mean brightness (0-255) count as no signal
+    pub black_threshold: f64,
+    /// Frames with a standard deviation below this value are a uniform color (e.g. a blue
+    /// screen) and count as no signal
+    pub uniform_threshold: f64,
+    /// Image of the placeholder screen the grabber shows without a signal. Relative paths are
+    /// relative to the settings file.
+    pub placeholder_image: Option<PathBuf>,
+    /// Frames that differ less than this from the placeholder image count as no signal
+    pub placeholder_threshold: f64,
+    /// Seconds without signal before the LEDs are turned off
+    pub no_signal_timeout_secs: u64,
+    /// Frames that differ less than this from the previous frame count as static
+    pub static_threshold: f64,
+    /// Seconds of static frames (paused video, screensaver) before the LEDs are turned off
+    pub static_timeout_secs: u64,
+    /// Duration of the fade out in milliseconds. Also used when rustylight is stopped.
+    pub fade_out_ms: u64,
+}
+
+impl Default for AutoOffSettings {
+    fn default() -> Self {
+        AutoOffSettings {
+            enabled: false,
+            black_threshold: 8.0,
+            uniform_threshold: 2.0,
+            placeholder_image: None,
+            placeholder_threshold: 6.0,
+            no_signal_timeout_secs: 10,
+            static_threshold: 1.0,
+            static_timeout_secs: 600,
+            fade_out_ms: 2000,
+        }
+    }
+}
+
+/// Effects that produce LED colors without a video source
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EffectKind {
+    StaticColor,
+    Breathing,
+    Rainbow,
+    ColorWipe,
+    Candle,
+    KnightRider,
+    /// Shows the edges and the direction of the lightstrip to check the layout settings
+    TestPattern,
+    /// User defined effect from a script in the effects directory
+    Script,
+}
+
+/// Extension of effect scripts
+pub const SCRIPT_EXTENSION: &str = "rhai";
+
+/// An effect with its parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectSettings {
+    pub effect: EffectKind,
+    /// Speed factor. 1 is the normal speed of the effect.
+    #[serde(default = "EffectSettings::default_speed")]
+    pub speed: f32,
+    /// Colors used by the effect as RGB. Effects that need a color use a default if none is
+    /// given.
+    #[serde(default)]
+    pub colors: Vec<[u8; 3]>,
+    /// Brightness between 0 and 1
+    #[serde(default = "EffectSettings::default_brightness")]
+    pub brightness: f32,
+    /// Name of the script without extension. Only used by script effects.
+    pub script: Option<String>,
+}
+
+impl EffectSettings {
+    pub fn new(effect: EffectKind) -> Self {
+        EffectSettings {
+            effect,
+            speed: Self::default_speed(),
+            colors: Vec::new(),
+            brightness: Self::default_brightness(),
+            script: None,
+        }
+    }
+
+    /// Path of the script of a script effect. Only plain names of files in the effects
+    /// directory are accepted so a request can not read files elsewhere.
+    pub fn script_path(&self) -> Result<PathBuf> {
+        let name = self
+            .script
+            .as_deref()
+            .ok_or_else(|| anyhow!("Script effects need the name of a script"))?;
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file)), None) if file == name => {}
+            _ => return Err(anyhow!("Invalid script name {:?}", name)),
+        }
+
+        let mut path = Settings::effects_dir();
+        path.push(name);
+        path.set_extension(SCRIPT_EXTENSION);
+        Ok(path)
+    }
+
+    /// Check values that can be parsed but not be used
+    pub fn validate(&self) -> Result<()> {
+        if let EffectKind::Script = self.effect {
+            self.script_path()?;
+        }
+        Ok(())
+    }
+
+    fn default_speed() -> f32 {
+        1.0
+    }
+
+    fn default_brightness() -> f32 {
+        1.0
+    }
+}
+
+/// What drives the LEDs
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// Colors of the captured video
+    #[default]
+    Video,
+    /// Visualization of the audio input
+    Audio,
+    /// Colors of the captured video modulated by the audio input
+    Hybrid,
+}
+
+/// Where the audio is read from
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioInput {
+    /// Monitor of a PulseAudio sink, recorded with parec
+    #[default]
+    PulseAudio,
+    /// ALSA capture device, recorded with arecord
+    Alsa,
+    /// Raw signed 16 bit little endian PCM on stdin
+    Stdin,
+    /// WAV file that is played in real time
+    File,
+}
+
+/// How the audio is shown on the LEDs
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visualization {
+    /// Frequency bands around the frame with the bass at the bottom and the treble at the top
+    #[default]
+    Spectrum,
+    /// Loudness that rises from the bottom corners to the top
+    VuMeter,
+    /// All LEDs flash on every beat
+    BeatFlash,
+}
+
+/// Settings of the audio visualizer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioSettings {
+    pub input: AudioInput,
+    /// WAV file for the file input
+    pub path: Option<PathBuf>,
+    /// Device for PulseAudio or ALSA. The default device is used if none is given.
+    pub device: Option<String>,
+    /// Sample rate of raw PCM input
+    pub sample_rate: u32,
+    /// Interleaved channels of raw PCM input
+    pub channels: u16,
+    pub visualization: Visualization,
+    /// Scales the measured levels. Increase for quiet sources.
+    pub sensitivity: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            input: AudioInput::default(),
+            path: None,
+            device: None,
+            sample_rate: 44100,
+            channels: 2,
+            visualization: Visualization::default(),
+            sensitivity: 1.0,
+        }
+    }
+}
+
+/// How the audio modulates the video colors in hybrid mode
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct HybridSettings {
+    /// Additional brightness on a beat. 0.3 makes the LEDs 30% brighter.
+    pub beat_pulse: f32,
+    /// Time in milliseconds in which a beat pulse fades to a third
+    pub pulse_decay_ms: u64,
+    /// Additional saturation at full loudness
+    pub saturation_boost: f32,
+}
+
+impl Default for HybridSettings {
+    fn default() -> Self {
+        HybridSettings {
+            beat_pulse: 0.3,
+            pulse_decay_ms: 250,
+            saturation_boost: 0.5,
+        }
+    }
+}
+
+/// Where the LED colors are written to
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum OutputDevice {
+    /// WS281x lightstrip connected to GPIO 18 of a Raspberry Pi
+    #[default]
+    Ws2812,
+    /// Colored blocks in the terminal. Useful for testing without a lightstrip.
+    Terminal,
+}
+
+/// Embedded HTTP server to control rustylight remotely
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpSettings {
+    /// Address and port the server listens on, e.g. "0.0.0.0:8080"
+    pub address: String,
+}
+
+/// Connection to an MQTT broker. Rustylight shows up as a light in Home Assistant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttSettings {
+    /// Host name or address of the broker
+    pub host: String,
+    #[serde(default = "MqttSettings::default_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Unique id of this rustylight. It is used as client id and as base of all topics.
+    #[serde(default = "MqttSettings::default_id")]
+    pub id: String,
+    /// Name of the light in Home Assistant
+    #[serde(default = "MqttSettings::default_name")]
+    pub name: String,
+    /// Prefix of the Home Assistant discovery topics
+    #[serde(default = "MqttSettings::default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+impl MqttSettings {
+    fn default_port() -> u16 {
+        1883
+    }
+
+    fn default_id() -> String {
+        "rustylight".to_string()
+    }
+
+    fn default_name() -> String {
+        "Rustylight".to_string()
+    }
+
+    fn default_discovery_prefix() -> String {
+        "homeassistant".to_string()
+    }
+}
+
+/// HomeKit accessory that can be added in the Home app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomeKitSettings {
+    /// Code that has to be entered while adding the accessory, e.g. "031-45-154"
+    pub setup_code: String,
+    #[serde(default = "HomeKitSettings::default_port")]
+    pub port: u16,
+    /// Name of the accessory in the Home app
+    #[serde(default = "HomeKitSettings::default_name")]
+    pub name: String,
+}
+
+impl HomeKitSettings {
+    fn default_port() -> u16 {
+        51826
+    }
+
+    fn default_name() -> String {
+        "Rustylight".to_string()
+    }
+}
+
+/// Local control socket for scripts and the rustylightctl client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocketSettings {
+    /// Path of the Unix domain socket
+    #[serde(default = "SocketSettings::default_path")]
+    pub path: PathBuf,
+}
+
+impl SocketSettings {
+    fn default_path() -> PathBuf {
+        crate::socket_path::default_path()
+    }
+}
+
+/// Live preview of the LEDs and the capture in the web interface
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct PreviewSettings {
+    /// How often the preview is updated per second
+    pub fps: f64,
+    /// Width in pixels of the capture snapshot. The height follows from the aspect ratio.
+    pub snapshot_width: i32,
+    /// JPEG quality of the capture snapshot between 0 and 100
+    pub jpeg_quality: i32,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        PreviewSettings {
+            fps: 10.0,
+            snapshot_width: 160,
+            jpeg_quality: 75,
+        }
+    }
+}
+
+/// Quantization range of the captured video
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum ColorRange {
+    /// Values use the whole range 0-255
+    #[default]
+    Full,
+    /// Values use the range 16-235 and will be expanded
+    Limited,
+    /// Detect the range from the observed black levels
+    Auto,
+}
+
+/// Corrects colors that have been converted from YUV with the wrong matrix
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum MatrixCorrection {
+    #[default]
+    Off,
+    /// The capture device used BT.601 but the content is BT.709 (usual for HD content)
+    Bt601ToBt709,
+    /// The capture device used BT.709 but the content is BT.601 (usual for SD content)
+    Bt709ToBt601,
+}
+
+/// Processing options that only apply to a specific video device
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputProfile {
+    pub video_device: i32,
+    #[serde(default)]
+    pub color_range: ColorRange,
+    #[serde(default)]
+    pub matrix_correction: MatrixCorrection,
+    pub tone_mapping: Option<ToneMappingSettings>,
+}
+
 /// Settings for rustylight that will be read from settings.toml file
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     pub log_level: LogLevel,
     pub video_device: i32,
-    pub capture_area_size: i32,
+    pub capture_area_size: CaptureAreaSize,
     pub processing_resolution: Resolution,
     pub start_corner: StartCorner,
     pub direction: Direction,
     pub led_count: i32,
+    #[serde(default)]
+    pub output: OutputDevice,
+    pub capture_quad: Option<CaptureQuad>,
+    #[serde(default)]
+    pub sampling_mode: SamplingModes,
+    #[serde(default)]
+    pub smoothing: SmoothingSettings,
+    #[serde(default)]
+    pub color_calibration: ColorCalibrationSettings,
+    pub lut: Option<LutSettings>,
+    #[serde(default)]
+    pub input_profiles: Vec<InputProfile>,
+    #[serde(default)]
+    pub power: PowerSettings,
+    /// Temporal dithering of the 16 bit colors for smoother fades at low brightness
+    #[serde(default)]
+    pub dithering: bool,
+    #[serde(default)]
+    pub auto_off: AutoOffSettings,
+    /// Effect that is shown while the capture is not active
+    pub idle_effect: Option<EffectSettings>,
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default)]
+    pub audio: AudioSettings,
+    #[serde(default)]
+    pub hybrid: HybridSettings,
+    pub http: Option<HttpSettings>,
+    pub mqtt: Option<MqttSettings>,
+    pub homekit: Option<HomeKitSettings>,
+    pub socket: Option<SocketSettings>,
+    #[serde(default)]
+    pub preview: PreviewSettings,
 }
 
+/// Settings file given on the command line. It replaces the default path.
+static CUSTOM_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 impl Settings {
     /// Read settings.toml file or create a new one with default values if it doesn't exist.
     pub fn new() -> Result<Self> {
-        // Setup path to settings file
-        let home_dir = env::var("HOME").expect("Could not find the HOME environment variable");
-        let mut settings_path = PathBuf::from(&home_dir);
-        settings_path.push(".config/rustylight/settings.toml");
+        let settings_path = Settings::path();
         println!("Attemting to read settings from {:?}", settings_path);
 
         if settings_path.exists() {
-            let settings_str = fs::read_to_string(settings_path)?;
-            let settings: Settings = toml::from_str(&settings_str)?;
+            let settings = Settings::load()?;
 
             println!("Successfully read settings from file!");
             Ok(settings)
@@ -94,23 +790,333 @@ impl Settings {
             }
 
             let settings = Settings::default();
-            let toml = toml::to_string(&settings)?;
-            fs::write(settings_path, toml)?;
+            settings.save()?;
             println!("Successfully created settings file!");
             Ok(settings)
         }
     }
 
+    /// Read the settings file without creating it
+    pub fn load() -> Result<Self> {
+        let settings_path = Settings::path();
+        if !settings_path.exists() {
+            return Err(anyhow!("Config file does not exist at {:?}", settings_path));
+        }
+
+        let settings_str = fs::read_to_string(&settings_path)
+            .with_context(|| format!("Could not read config file {:?}", settings_path))?;
+        toml::from_str(&settings_str)
+            .with_context(|| format!("Invalid config file {:?}", settings_path))
+    }
+
+    /// Check values that can be parsed but not be used
+    pub fn validate(&self) -> Result<()> {
+        if self.led_count <= 0 {
+            return Err(anyhow!("led_count has to be at least 1"));
+        }
+
+        if let CaptureAreaSize::PerEdge {
+            unit,
+            top,
+            right,
+            bottom,
+            left,
+        } = self.capture_area_size
+        {
+            for (name, depth) in [
+                ("top", top),
+                ("right", right),
+                ("bottom", bottom),
+                ("left", left),
+            ] {
+                check_non_negative(&format!("capture_area_size.{}", name), depth)?;
+            }
+            if matches!(unit, DepthUnit::Percent)
+                && (top + bottom >= 100.0 || left + right >= 100.0)
+            {
+                return Err(anyhow!(
+                    "capture_area_size: opposing edges must cover less than 100 percent"
+                ));
+            }
+        }
+
+        let smoothing = &self.smoothing;
+        check_positive("smoothing.update_rate", smoothing.update_rate)?;
+        check_non_negative("smoothing.decay", smoothing.decay)?;
+        if let Some(threshold) = smoothing.scene_cut_threshold {
+            check_range("smoothing.scene_cut_threshold", threshold as f64, 0.0, 1.0)?;
+        }
+
+        let calibration = &self.color_calibration;
+        for channel in 0..3 {
+            check_positive("color_calibration.gamma", calibration.gamma[channel] as f64)?;
+            check_non_negative("color_calibration.gain", calibration.gain[channel] as f64)?;
+        }
+        check_non_negative(
+            "color_calibration.brightness",
+            calibration.brightness as f64,
+        )?;
+        check_non_negative(
+            "color_calibration.saturation",
+            calibration.saturation as f64,
+        )?;
+
+        if let Some(lut) = &self.lut {
+            let path = Settings::resolve_path(&lut.path);
+            if !path.is_file() {
+                return Err(anyhow!("LUT file {:?} does not exist", path));
+            }
+        }
+
+        for profile in &self.input_profiles {
+            if let Some(tone_mapping) = &profile.tone_mapping {
+                check_positive("tone_mapping.white_nits", tone_mapping.white_nits as f64)?;
+                check_positive("tone_mapping.peak_nits", tone_mapping.peak_nits as f64)?;
+                check_non_negative("tone_mapping.saturation", tone_mapping.saturation as f64)?;
+                check_non_negative("tone_mapping.contrast", tone_mapping.contrast as f64)?;
+            }
+        }
+
+        let power = &self.power;
+        check_non_negative(
+            "power.milliamps_per_channel",
+            power.milliamps_per_channel as f64,
+        )?;
+        check_non_negative("power.idle_milliamps", power.idle_milliamps as f64)?;
+        if let Some(limit) = power.supply_limit_amps {
+            check_positive("power.supply_limit_amps", limit as f64)?;
+        }
+        check_range(
+            "power.max_brightness",
+            power.max_brightness as f64,
+            0.0,
+            1.0,
+        )?;
+
+        let auto_off = &self.auto_off;
+        check_non_negative("auto_off.black_threshold", auto_off.black_threshold)?;
+        check_non_negative("auto_off.uniform_threshold", auto_off.uniform_threshold)?;
+        check_non_negative(
+            "auto_off.placeholder_threshold",
+            auto_off.placeholder_threshold,
+        )?;
+        check_non_negative("auto_off.static_threshold", auto_off.static_threshold)?;
+        if let Some(path) = &auto_off.placeholder_image {
+            let path = Settings::resolve_path(path);
+            if !path.is_file() {
+                return Err(anyhow!("Placeholder image {:?} does not exist", path));
+            }
+        }
+
+        if let Some(effect) = &self.idle_effect {
+            effect.validate().context("Invalid idle_effect")?;
+        }
+
+        let audio = &self.audio;
+        check_positive("audio.sample_rate", audio.sample_rate as f64)?;
+        check_positive("audio.channels", audio.channels as f64)?;
+        check_non_negative("audio.sensitivity", audio.sensitivity as f64)?;
+        if audio.input == AudioInput::File && audio.path.is_none() {
+            return Err(anyhow!("The file input needs audio.path"));
+        }
+
+        let hybrid = &self.hybrid;
+        check_non_negative("hybrid.beat_pulse", hybrid.beat_pulse as f64)?;
+        check_non_negative("hybrid.saturation_boost", hybrid.saturation_boost as f64)?;
+
+        let preview = &self.preview;
+        check_positive("preview.fps", preview.fps)?;
+        check_positive("preview.snapshot_width", preview.snapshot_width as f64)?;
+        check_range(
+            "preview.jpeg_quality",
+            preview.jpeg_quality as f64,
+            0.0,
+            100.0,
+        )?;
+        Ok(())
+    }
+
+    /// Default settings as they are written to a new settings file
+    pub fn default_toml() -> Result<String> {
+        Ok(toml::to_string(&Settings::default())?)
+    }
+
+    /// Profile of the configured video device. If there is none the default profile without any
+    /// additional processing is returned.
+    pub fn input_profile(&self) -> InputProfile {
+        self.input_profiles
+            .iter()
+            .find(|profile| profile.video_device == self.video_device)
+            .cloned()
+            .unwrap_or_else(|| InputProfile {
+                video_device: self.video_device,
+                ..InputProfile::default()
+            })
+    }
+
+    /// Use a different settings file. Has to be called before the settings are read.
+    pub fn set_path(path: PathBuf) {
+        let _ = CUSTOM_PATH.set(path);
+    }
+
+    /// Path to the settings file
+    pub fn path() -> PathBuf {
+        if let Some(path) = CUSTOM_PATH.get() {
+            return path.clone();
+        }
+
+        let home_dir = env::var("HOME").expect("Could not find the HOME environment variable");
+        let mut settings_path = PathBuf::from(&home_dir);
+        settings_path.push(".config/rustylight/settings.toml");
+        settings_path
+    }
+
+    /// Paths in the settings are relative to the directory of the settings file
+    pub fn resolve_path(path: &Path) -> PathBuf {
+        let settings_path = Settings::path();
+        match settings_path.parent() {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Directory that contains the effect scripts
+    pub fn effects_dir() -> PathBuf {
+        Settings::resolve_path(Path::new("effects"))
+    }
+
+    /// Write the settings to the settings file
+    pub fn save(&self) -> Result<()> {
+        let toml = toml::to_string(self)?;
+        fs::write(Settings::path(), toml)?;
+        Ok(())
+    }
+
     /// Create default settings. They can be changed later in the file.
     fn default() -> Settings {
         Settings {
             log_level: LogLevel::Info,
             video_device: 0,
-            capture_area_size: 10,
+            capture_area_size: CaptureAreaSize::PerEdge {
+                unit: DepthUnit::Percent,
+                top: 2.0,
+                right: 2.0,
+                bottom: 2.0,
+                left: 2.0,
+            },
             processing_resolution: Resolution::VGA,
             start_corner: StartCorner::BL,
             direction: Direction::CW,
             led_count: 123,
+            output: OutputDevice::default(),
+            capture_quad: None,
+            sampling_mode: SamplingModes::default(),
+            smoothing: SmoothingSettings::default(),
+            color_calibration: ColorCalibrationSettings::default(),
+            lut: None,
+            input_profiles: Vec::new(),
+            power: PowerSettings::default(),
+            dithering: false,
+            auto_off: AutoOffSettings::default(),
+            idle_effect: None,
+            mode: Mode::default(),
+            audio: AudioSettings::default(),
+            hybrid: HybridSettings::default(),
+            http: None,
+            mqtt: None,
+            homekit: None,
+            socket: None,
+            preview: PreviewSettings::default(),
         }
     }
 }
+
+/// Error naming the setting unless the value is finite and between min and max
+fn check_range(name: &str, value: f64, min: f64, max: f64) -> Result<()> {
+    if value.is_finite() && (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} has to be between {} and {} but is {}",
+            name,
+            min,
+            max,
+            value
+        ))
+    }
+}
+
+fn check_positive(name: &str, value: f64) -> Result<()> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{} has to be larger than 0 but is {}", name, value))
+    }
+}
+
+fn check_non_negative(name: &str, value: f64) -> Result<()> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{} must not be negative but is {}", name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(settings: &Settings) -> String {
+        settings.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Settings::default().validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unusable_calibration() {
+        let mut settings = Settings::default();
+        settings.color_calibration.gamma[1] = 0.0;
+        assert!(error(&settings).starts_with("color_calibration.gamma"));
+
+        let mut settings = Settings::default();
+        settings.color_calibration.gain[2] = f32::NAN;
+        assert!(error(&settings).starts_with("color_calibration.gain"));
+    }
+
+    #[test]
+    fn rejects_unusable_power_settings() {
+        let mut settings = Settings::default();
+        settings.power.max_brightness = 1.5;
+        assert!(error(&settings).starts_with("power.max_brightness"));
+
+        let mut settings = Settings::default();
+        settings.power.supply_limit_amps = Some(0.0);
+        assert!(error(&settings).starts_with("power.supply_limit_amps"));
+    }
+
+    #[test]
+    fn rejects_overlapping_capture_area() {
+        let mut settings = Settings::default();
+        settings.capture_area_size = CaptureAreaSize::PerEdge {
+            unit: DepthUnit::Percent,
+            top: 50.0,
+            right: 10.0,
+            bottom: 50.0,
+            left: 10.0,
+        };
+        assert!(error(&settings).starts_with("capture_area_size"));
+    }
+
+    #[test]
+    fn rejects_missing_lut() {
+        let mut settings = Settings::default();
+        settings.lut = Some(LutSettings {
+            path: PathBuf::from("/nonexistent/rustylight.cube"),
+            interpolation: LutInterpolation::default(),
+        });
+        assert!(error(&settings).contains("does not exist"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use notify::{Event, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use crate::control::Control;
+use crate::settings::Settings;
+
+/// Editors write a file in several steps. The settings are read once the file has not changed
+/// for this long.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Apply changes of the settings file while rustylight is running. The settings are read with
+/// reload. If they are invalid or can not be applied the previous settings stay active.
+pub fn spawn<F>(control: Control, reload: F) -> Result<()>
+where
+    F: Fn() -> Result<Settings> + Send + 'static,
+{
+    let path = Settings::path();
+    let file_name = path
+        .file_name()
+        .map(OsString::from)
+        .ok_or_else(|| anyhow!("Invalid settings path {:?}", path))?;
+    // The directory is watched because many editors replace the file instead of writing to it
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    let (sender, receiver) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    info!("Watching {:?} for changes", path);
+
+    let mut previous = fs::read_to_string(&path).ok();
+    thread::spawn(move || {
+        // Events are only sent as long as the watcher exists
+        let _watcher = watcher;
+
+        for event in receiver.iter() {
+            match event {
+                Ok(event) => {
+                    let affected = event
+                        .paths
+                        .iter()
+                        .any(|changed| changed.file_name() == Some(file_name.as_os_str()));
+                    if !affected {
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    warn!("Could not watch settings file: {}", e);
+                    continue;
+                }
+            }
+
+            // Wait until the file has been written completely
+            while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+
+            // Saving without changes or removing the file is ignored
+            let contents = fs::read_to_string(&path).ok();
+            if contents.is_none() || contents == previous {
+                continue;
+            }
+            previous = contents;
+
+            info!("Settings file changed");
+            control.reload_settings(&reload);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use opencv::{
+    core::{self, Mat, MatTraitConst, Size},
+    imgcodecs, imgproc,
+};
+use tracing::{debug, info};
+
+use crate::settings::{AutoOffSettings, Settings};
+
+/// Frames are compared at this small size. This is fast and ignores noise of the capture.
+const THUMBNAIL_SIZE: (i32, i32) = (32, 18);
+
+/// What the source is currently showing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignalState {
+    /// Normal content
+    Active,
+    /// Black frames, a uniform color or the placeholder screen of the grabber
+    NoSignal,
+    /// The picture did not change for a long time (paused video, screensaver)
+    Static,
+}
+
+/// Detects frames without signal and static frames. Only after the condition has lasted for the
+/// configured timeout the state changes. Any change of the picture makes the source active again.
+pub struct SignalDetector {
+    settings: AutoOffSettings,
+    placeholder: Option<Mat>,
+    previous: Mat,
+    thumbnail: Mat,
+    no_signal_since: Option<Instant>,
+    static_since: Option<Instant>,
+    state: SignalState,
+}
+
+impl SignalDetector {
+    pub fn new(settings: AutoOffSettings) -> Result<Self> {
+        let placeholder = match &settings.placeholder_image {
+            Some(path) => {
+                let path = Settings::resolve_path(path);
+                let image =
+                    imgcodecs::imread(path.to_str().unwrap_or_default(), imgcodecs::IMREAD_COLOR)?;
+                if image.empty() {
+                    return Err(anyhow!("Could not read placeholder image {:?}", path));
+                }
+                info!("Using placeholder image {:?} for no signal detection", path);
+                Some(Self::to_thumbnail(&image)?)
+            }
+            None => None,
+        };
+
+        Ok(SignalDetector {
+            settings,
+            placeholder,
+            previous: Mat::default(),
+            thumbnail: Mat::default(),
+            no_signal_since: None,
+            static_since: None,
+            state: SignalState::Active,
+        })
+    }
+
+    pub fn state(&self) -> SignalState {
+        self.state
+    }
+
+    /// Inspect a frame and return the new state if it changed
+    pub fn update(&mut self, frame: &Mat, now: Instant) -> Result<Option<SignalState>> {
+        if !self.settings.enabled {
+            return Ok(None);
+        }
+
+        self.thumbnail = Self::to_thumbnail(frame)?;
+
+        let no_signal = self.is_no_signal()?;
+        let is_static = !self.previous.empty()
+            && self.difference(&self.previous)? < self.settings.static_threshold;
+        std::mem::swap(&mut self.previous, &mut self.thumbnail);
+
+        let no_signal_for = Self::track(&mut self.no_signal_since, no_signal, now);
+        let static_for = Self::track(&mut self.static_since, is_static, now);
+
+        let no_signal_timeout = Duration::from_secs(self.settings.no_signal_timeout_secs);
+        let static_timeout = Duration::from_secs(self.settings.static_timeout_secs);
+
+        let state = if no_signal && no_signal_for >= no_signal_timeout {
+            SignalState::NoSignal
+        } else if is_static && static_for >= static_timeout {
+            SignalState::Static
+        } else {
+            SignalState::Active
+        };
+
+        if state == self.state {
+            return Ok(None);
+        }
+
+        info!("Source changed from {:?} to {:?}", self.state, state);
+        self.state = state;
+        Ok(Some(state))
+    }
+
+    /// Black frames, uniformly colored frames and the placeholder screen count as no signal
+    fn is_no_signal(&self) -> Result<bool> {
+        let mut mean = Mat::default();
+        let mut std_dev = Mat::default();
+        core::mean_std_dev(&self.thumbnail, &mut mean, &mut std_dev, &core::no_array())?;
+
+        // Both hold one value per channel
+        let mut brightness = 0.0;
+        let mut deviation: f64 = 0.0;
+        for channel in 0..3 {
+            brightness += *mean.at::<f64>(channel)? / 3.0;
+            deviation = deviation.max(*std_dev.at::<f64>(channel)?);
+        }
+        debug!(
+            "Frame brightness: {:.1}, deviation: {:.1}",
+            brightness, deviation
+        );
+
+        if brightness < self.settings.black_threshold || deviation < self.settings.uniform_threshold
+        {
+            return Ok(true);
+        }
+
+        match &self.placeholder {
+            Some(placeholder) => {
+                Ok(self.difference(placeholder)? < self.settings.placeholder_threshold)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Mean absolute difference between the current thumbnail and another one
+    fn difference(&self, other: &Mat) -> Result<f64> {
+        let mut diff = Mat::default();
+        core::absdiff(&self.thumbnail, other, &mut diff)?;
+        let mean = core::mean(&diff, &core::no_array())?;
+        Ok((mean[0] + mean[1] + mean[2]) / 3.0)
+    }
+
+    /// Returns for how long the condition has been true
+    fn track(since: &mut Option<Instant>, condition: bool, now: Instant) -> Duration {
+        if !condition {
+            *since = None;
+            return Duration::ZERO;
+        }
+        now.saturating_duration_since(*since.get_or_insert(now))
+    }
+
+    fn to_thumbnail(frame: &Mat) -> Result<Mat> {
+        let mut thumbnail = Mat::default();
+        imgproc::resize(
+            frame,
+            &mut thumbnail,
+            Size::new(THUMBNAIL_SIZE.0, THUMBNAIL_SIZE.1),
+            0.0,
+            0.0,
+            imgproc::INTER_AREA,
+        )?;
+        Ok(thumbnail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{MatTrait, Scalar, Vec3b, CV_8UC3};
+
+    fn settings() -> AutoOffSettings {
+        AutoOffSettings {
+            enabled: true,
+            no_signal_timeout_secs: 10,
+            static_timeout_secs: 60,
+            ..Default::default()
+        }
+    }
+
+    fn uniform(value: f64) -> Mat {
+        Mat::new_rows_cols_with_default(36, 64, CV_8UC3, Scalar::all(value)).unwrap()
+    }
+
+    /// Frame with a gradient. Frames with a different shift differ clearly.
+    fn content(shift: u8) -> Mat {
+        let mut frame = uniform(0.0);
+        for row in 0..36 {
+            for col in 0..64 {
+                *frame.at_2d_mut::<Vec3b>(row, col).unwrap() =
+                    Vec3b::from_array([(col as u8 * 4).wrapping_add(shift), row as u8 * 5, 100]);
+            }
+        }
+        frame
+    }
+
+    fn seconds(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn black_frames_are_no_signal_after_the_timeout() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        let black = uniform(0.0);
+
+        assert_eq!(detector.update(&black, start).unwrap(), None);
+        assert_eq!(detector.update(&black, seconds(start, 9)).unwrap(), None);
+        assert_eq!(
+            detector.update(&black, seconds(start, 10)).unwrap(),
+            Some(SignalState::NoSignal)
+        );
+        assert_eq!(detector.state(), SignalState::NoSignal);
+    }
+
+    #[test]
+    fn uniform_frames_are_no_signal() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        let blue_screen =
+            Mat::new_rows_cols_with_default(36, 64, CV_8UC3, Scalar::new(200.0, 0.0, 0.0, 0.0))
+                .unwrap();
+
+        detector.update(&blue_screen, start).unwrap();
+        assert_eq!(
+            detector.update(&blue_screen, seconds(start, 10)).unwrap(),
+            Some(SignalState::NoSignal)
+        );
+    }
+
+    #[test]
+    fn content_is_active() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        for second in 0..20 {
+            let frame = content((second * 40 % 256) as u8);
+            assert_eq!(
+                detector.update(&frame, seconds(start, second)).unwrap(),
+                None
+            );
+        }
+        assert_eq!(detector.state(), SignalState::Active);
+    }
+
+    #[test]
+    fn short_interruptions_restart_the_timeout() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        let black = uniform(0.0);
+
+        detector.update(&black, start).unwrap();
+        detector.update(&black, seconds(start, 8)).unwrap();
+        detector.update(&content(0), seconds(start, 9)).unwrap();
+        assert_eq!(detector.update(&black, seconds(start, 10)).unwrap(), None);
+        assert_eq!(detector.update(&black, seconds(start, 19)).unwrap(), None);
+        assert_eq!(
+            detector.update(&black, seconds(start, 20)).unwrap(),
+            Some(SignalState::NoSignal)
+        );
+    }
+
+    #[test]
+    fn content_makes_the_source_active_again() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        let black = uniform(0.0);
+
+        detector.update(&black, start).unwrap();
+        detector.update(&black, seconds(start, 10)).unwrap();
+        assert_eq!(
+            detector.update(&content(0), seconds(start, 11)).unwrap(),
+            Some(SignalState::Active)
+        );
+    }
+
+    #[test]
+    fn unchanged_frames_are_static_after_the_timeout() {
+        let mut detector = SignalDetector::new(settings()).unwrap();
+        let start = Instant::now();
+        let paused = content(0);
+
+        // The first frame has nothing to compare with, so the static time starts with the second
+        for second in 0..61 {
+            assert_eq!(
+                detector.update(&paused, seconds(start, second)).unwrap(),
+                None
+            );
+        }
+        assert_eq!(
+            detector.update(&paused, seconds(start, 61)).unwrap(),
+            Some(SignalState::Static)
+        );
+        assert_eq!(
+            detector.update(&content(40), seconds(start, 62)).unwrap(),
+            Some(SignalState::Active)
+        );
+    }
+
+    #[test]
+    fn disabled_detector_never_changes_the_state() {
+        let mut settings = settings();
+        settings.enabled = false;
+        let mut detector = SignalDetector::new(settings).unwrap();
+        let start = Instant::now();
+        let black = uniform(0.0);
+
+        detector.update(&black, start).unwrap();
+        assert_eq!(detector.update(&black, seconds(start, 60)).unwrap(), None);
+        assert_eq!(detector.state(), SignalState::Active);
+    }
+}
@@ -0,0 +1,55 @@
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::Result;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tracing::{info, warn};
+
+use crate::control::{Command, Control};
+use crate::settings::Settings;
+
+/// Signals that stop rustylight
+const SHUTDOWN: [i32; 2] = [SIGINT, SIGTERM];
+
+/// Pass signals to the main loop. SIGINT and SIGTERM turn the LEDs off and stop rustylight,
+/// SIGHUP applies the settings returned by reload. A second SIGINT or SIGTERM exits right away
+/// in case the shutdown hangs.
+pub fn spawn<F>(control: Control, reload: F) -> Result<()>
+where
+    F: Fn() -> Result<Settings> + Send + 'static,
+{
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+
+    thread::spawn(move || {
+        let mut shutting_down = false;
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                info!("Reloading settings");
+                control.reload_settings(&reload);
+            } else if shutting_down {
+                warn!("Exiting without turning the LEDs off");
+                process::exit(1);
+            } else {
+                info!("Shutting down");
+                shutting_down = true;
+                if control.send(Command::Shutdown).is_err() {
+                    process::exit(1);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Flag that is set once SIGINT or SIGTERM has been received. Used by the commands that do not
+/// run the main loop.
+pub fn shutdown_flag() -> Result<Arc<AtomicBool>> {
+    let flag = Arc::new(AtomicBool::new(false));
+    for signal in SHUTDOWN {
+        signal_hook::flag::register(signal, Arc::clone(&flag))?;
+    }
+    Ok(flag)
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use smart_leds::RGB16;
+
+use crate::settings::{SmoothingAlgorithm, SmoothingSettings};
+
+/// Color with a higher precision than RGB16 so the smoothing does not get stuck on rounding
+type Color = [f32; 3];
+
+/// Smooths the LED colors between two captured frames. New frames are set as target and the
+/// smoothed colors are requested at the update rate of the output.
+pub struct Smoother {
+    settings: SmoothingSettings,
+    /// Colors that have been returned by the last update
+    current: Vec<Color>,
+    /// Colors at the moment the latest target has been set. Used by the linear smoothing.
+    start: Vec<Color>,
+    /// Latest captured frame
+    target: Vec<Color>,
+    target_time: Instant,
+    /// Captured frames within the time window. Used by the decay smoothing.
+    history: VecDeque<(Instant, Vec<Color>)>,
+    last_update: Instant,
+}
+
+impl Smoother {
+    pub fn new(settings: SmoothingSettings, led_count: usize) -> Self {
+        let now = Instant::now();
+        Smoother {
+            settings,
+            current: vec![[0.0; 3]; led_count],
+            start: vec![[0.0; 3]; led_count],
+            target: vec![[0.0; 3]; led_count],
+            target_time: now,
+            history: VecDeque::new(),
+            last_update: now,
+        }
+    }
+
+    /// Set a newly captured frame as the target of the smoothing
+    pub fn set_target(&mut self, leds: &[RGB16], now: Instant) {
+        self.start.clone_from(&self.current);
+        self.target = leds.iter().map(|led| to_color(*led)).collect();
+        self.target_time = now;
+
+        if let SmoothingAlgorithm::Decay = self.settings.algorithm {
+            self.history.push_back((now, self.target.clone()));
+        }
+    }
+
+    /// Show the target immediately. Used when the smoothing shall be bypassed.
+    pub fn snap(&mut self) {
+        self.current.clone_from(&self.target);
+        self.start.clone_from(&self.target);
+        self.history.clear();
+    }
+
+    /// Calculate the colors that shall be shown at the given time
+    pub fn update(&mut self, now: Instant) -> Vec<RGB16> {
+        let window = Duration::from_millis(self.settings.time_window_ms);
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+
+        match self.settings.algorithm {
+            SmoothingAlgorithm::Off => self.current.clone_from(&self.target),
+            SmoothingAlgorithm::Linear => {
+                let progress = if window.is_zero() {
+                    1.0
+                } else {
+                    let since_target = now.saturating_duration_since(self.target_time);
+                    (since_target.as_secs_f32() / window.as_secs_f32()).min(1.0)
+                };
+                self.current = mix(&self.start, &self.target, progress);
+            }
+            SmoothingAlgorithm::Ema => {
+                // Weight of the target so the result does not depend on the update rate
+                let alpha = if window.is_zero() {
+                    1.0
+                } else {
+                    1.0 - (-elapsed.as_secs_f32() / window.as_secs_f32()).exp()
+                };
+                self.current = mix(&self.current, &self.target, alpha);
+            }
+            SmoothingAlgorithm::Decay => self.decay(now, window),
+        }
+
+        self.current.iter().map(|color| to_rgb16(*color)).collect()
+    }
+
+    /// Weighted average of the frames within the time window. The weight of a frame falls from 1
+    /// to 0 over the time window.
+    fn decay(&mut self, now: Instant, window: Duration) {
+        // Always keep the latest frame, even if it is older than the window
+        while self.history.len() > 1 && now.saturating_duration_since(self.history[0].0) > window {
+            self.history.pop_front();
+        }
+
+        let mut sum = vec![[0.0f32; 3]; self.target.len()];
+        let mut total_weight = 0.0;
+
+        for (time, frame) in self.history.iter() {
+            let age = now.saturating_duration_since(*time).as_secs_f64();
+            let weight = if window.is_zero() {
+                1.0
+            } else {
+                (1.0 - age / window.as_secs_f64())
+                    .max(0.0)
+                    .powf(self.settings.decay) as f32
+            };
+
+            for (sum, color) in sum.iter_mut().zip(frame.iter()) {
+                for (sum, value) in sum.iter_mut().zip(color.iter()) {
+                    *sum += value * weight;
+                }
+            }
+            total_weight += weight;
+        }
+
+        if total_weight > 0.0 {
+            self.current = sum
+                .into_iter()
+                .map(|color| color.map(|value| value / total_weight))
+                .collect();
+        } else {
+            self.current.clone_from(&self.target);
+        }
+    }
+}
+
+/// Linear interpolation between two frames
+fn mix(from: &[Color], to: &[Color], amount: f32) -> Vec<Color> {
+    from.iter()
+        .zip(to.iter())
+        .map(|(from, to)| {
+            [
+                from[0] + (to[0] - from[0]) * amount,
+                from[1] + (to[1] - from[1]) * amount,
+                from[2] + (to[2] - from[2]) * amount,
+            ]
+        })
+        .collect()
+}
+
+fn to_color(led: RGB16) -> Color {
+    [led.r as f32, led.g as f32, led.b as f32]
+}
+
+fn to_rgb16(color: Color) -> RGB16 {
+    let channel = |value: f32| value.round().clamp(0.0, u16::MAX as f32) as u16;
+    RGB16 {
+        r: channel(color[0]),
+        g: channel(color[1]),
+        b: channel(color[2]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: RGB16 = RGB16 {
+        r: u16::MAX,
+        g: u16::MAX,
+        b: u16::MAX,
+    };
+
+    fn smoother(algorithm: SmoothingAlgorithm) -> Smoother {
+        let settings = SmoothingSettings {
+            algorithm,
+            time_window_ms: 100,
+            ..Default::default()
+        };
+        Smoother::new(settings, 2)
+    }
+
+    /// Captures a black frame and 10 ms later a white one. Returns the red channel of the first
+    /// LED every 10 ms over one second, which is ten time windows.
+    fn step_response(smoother: &mut Smoother, start: Instant) -> Vec<u16> {
+        smoother.set_target(&[RGB16::default(); 2], start);
+        smoother.update(start);
+        let step = start + Duration::from_millis(10);
+        smoother.set_target(&[WHITE; 2], step);
+        (1..=100)
+            .map(|update| smoother.update(step + Duration::from_millis(10 * update))[0].r)
+            .collect()
+    }
+
+    #[test]
+    fn step_input_converges() {
+        for algorithm in [
+            SmoothingAlgorithm::Linear,
+            SmoothingAlgorithm::Ema,
+            SmoothingAlgorithm::Decay,
+        ] {
+            let response = step_response(&mut smoother(algorithm), Instant::now());
+            assert!(
+                response[0] > 0 && response[0] < u16::MAX,
+                "{:?} jumped to {}",
+                algorithm,
+                response[0]
+            );
+            // Allow for rounding
+            assert!(
+                response
+                    .windows(2)
+                    .all(|pair| pair[0] <= pair[1].saturating_add(1)),
+                "{:?} is not monotonic: {:?}",
+                algorithm,
+                response
+            );
+            assert!(
+                *response.last().unwrap() >= u16::MAX - u16::MAX / 1000,
+                "{:?} did not converge",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn off_shows_the_target_immediately() {
+        let response = step_response(&mut smoother(SmoothingAlgorithm::Off), Instant::now());
+        assert!(response.iter().all(|value| *value == u16::MAX));
+    }
+
+    #[test]
+    fn scene_cut_resets_the_filter() {
+        for algorithm in [
+            SmoothingAlgorithm::Linear,
+            SmoothingAlgorithm::Ema,
+            SmoothingAlgorithm::Decay,
+        ] {
+            let mut smoother = smoother(algorithm);
+            let start = Instant::now();
+            step_response(&mut smoother, start);
+
+            // Cut from white to black
+            let cut = start + Duration::from_secs(1);
+            smoother.set_target(&[RGB16::default(); 2], cut);
+            smoother.snap();
+            assert_eq!(
+                smoother.update(cut),
+                [RGB16::default(); 2],
+                "{:?}",
+                algorithm
+            );
+            assert_eq!(
+                smoother.update(cut + Duration::from_millis(10)),
+                [RGB16::default(); 2],
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::control::{Command, Control, State};
+use crate::effects::{self, Summary};
+use crate::settings::{EffectKind, EffectSettings, SocketSettings};
+
+/// Request of a client. Every request is a JSON object on its own line, e.g.
+/// `{"command": "brightness", "value": 40}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", content = "value", rename_all = "snake_case")]
+enum Request {
+    On,
+    Off,
+    /// Brightness in percent
+    Brightness(f32),
+    /// Hex color like "ff8800" that is shown as static color
+    Color(String),
+    /// Name of an effect or a script
+    Effect(String),
+    Status,
+}
+
+/// Start the control socket. Every client gets its own thread and one JSON response per request.
+pub fn spawn(settings: &SocketSettings, control: Control) -> Result<()> {
+    let path = &settings.path;
+    // The default directory only exists once a settings file has been created there
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Could not create directory {:?}", directory))?;
+    }
+    remove_stale(path)?;
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Could not create control socket {:?}", path))?;
+    info!("Control socket listening on {:?}", path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let control = control.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &control) {
+                            debug!("Control socket client failed: {:#}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket connection failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Remove a socket file that has been left behind by a previous run. The socket of a running
+/// rustylight is kept.
+fn remove_stale(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if UnixStream::connect(path).is_ok() {
+        bail!("Another rustylight is already listening on {:?}", path);
+    }
+    fs::remove_file(path).with_context(|| format!("Could not remove old socket {:?}", path))
+}
+
+/// Answer the requests of a client until it disconnects
+fn serve(stream: UnixStream, control: &Control) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match handle(&line, control) {
+            Ok(response) => response,
+            Err(e) => json!({ "ok": false, "error": format!("{:#}", e) }),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+fn handle(line: &str, control: &Control) -> Result<Value> {
+    let request: Request = serde_json::from_str(line).context("Invalid request")?;
+    debug!("Control socket request: {:?}", request);
+
+    match request {
+        Request::On => control.send(Command::SetEnabled(true))?,
+        Request::Off => control.send(Command::SetEnabled(false))?,
+        Request::Brightness(percent) => {
+            if !(0.0..=100.0).contains(&percent) {
+                bail!("Brightness must be between 0 and 100");
+            }
+            control.send(Command::SetBrightness(percent / 100.0))?;
+        }
+        Request::Color(hex) => {
+            let mut effect = EffectSettings::new(EffectKind::StaticColor);
+            effect.colors = vec![parse_color(&hex)?];
+            control.send(Command::SetEffect(Some(effect)))?;
+        }
+        Request::Effect(name) => control.send(Command::SetEffect(effects::by_name(&name)?))?,
+        Request::Status => return Ok(json!({ "ok": true, "state": status(&control.state()) })),
+    }
+    Ok(json!({ "ok": true }))
+}
+
+/// Color as six hex digits with an optional leading '#'
+fn parse_color(hex: &str) -> Result<[u8; 3]> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!(
+            "Invalid color {:?}, expected six hex digits like ff8800",
+            hex
+        );
+    }
+
+    let channel = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16);
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn status(state: &State) -> Value {
+    let summary = Summary::new(state);
+    let [r, g, b] = summary.color;
+
+    json!({
+        "enabled": summary.enabled,
+        "brightness": (summary.brightness * 100.0).round() as u8,
+        "effect": summary.effect,
+        "color": format!("{:02x}{:02x}{:02x}", r, g, b),
+    })
+}
@@ -0,0 +1,19 @@
+use std::env;
+use std::path::PathBuf;
+
+const FILE_NAME: &str = "rustylight.sock";
+
+/// Default path of the control socket, also used by rustylightctl. The socket is created in the
+/// runtime directory of the user because other users could block a fixed path in /tmp. Without
+/// a runtime directory it is created next to the default settings file.
+pub fn default_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(FILE_NAME),
+        _ => {
+            let home_dir = env::var("HOME").expect("Could not find the HOME environment variable");
+            PathBuf::from(home_dir)
+                .join(".config/rustylight")
+                .join(FILE_NAME)
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use anyhow::Result;
+
+use crate::output::Output;
+use crate::settings::Settings;
+
+/// Anything that drives the LEDs from an input, like the video capture or the audio visualizer
+pub trait LedSource {
+    /// Process the next piece of input and send the colors to the output. Returns false when the
+    /// input has ended.
+    fn process(&mut self, output: &mut Output) -> Result<bool>;
+
+    /// Apply new settings. If they can not be applied the previous settings stay active.
+    fn reconfigure(&mut self, settings: &Settings) -> Result<()>;
+
+    /// Turn the source on or off. A disabled source fades the LEDs out.
+    fn set_enabled(&mut self, enabled: bool, output: &mut Output) -> Result<()>;
+
+    /// Release the input device before rustylight exits. Inputs that do not need to be released
+    /// explicitly are closed when the source is dropped.
+    fn release(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
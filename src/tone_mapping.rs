@@ -0,0 +1,95 @@
+use opencv::core::Vec3b;
+
+use crate::settings::ToneMappingSettings;
+
+// Constants of the SMPTE ST 2084 (PQ) transfer function
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+/// Peak brightness of PQ in nits
+const PQ_PEAK_NITS: f32 = 10000.0;
+
+/// Converts linear BT.2020 to linear BT.709 (rows are red, green, blue)
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+/// Rec. 709 luma coefficients for red, green and blue
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Gamma used to encode the SDR result
+const SDR_GAMMA: f32 = 2.2;
+
+/// Maps captured HDR10 colors to SDR. The colors are expected as the raw PQ signal in BT.2020,
+/// which is what many grabbers deliver when they do not convert HDR themselves.
+pub struct ToneMapper {
+    settings: ToneMappingSettings,
+    /// Linear brightness relative to SDR white for each 8 bit PQ value
+    pq_to_linear: [f32; 256],
+}
+
+impl ToneMapper {
+    pub fn new(settings: ToneMappingSettings) -> Self {
+        let mut pq_to_linear = [0.0; 256];
+        for (value, linear) in pq_to_linear.iter_mut().enumerate() {
+            *linear = pq_eotf(value as f32 / 255.0) / settings.white_nits.max(1.0);
+        }
+
+        ToneMapper {
+            settings,
+            pq_to_linear,
+        }
+    }
+
+    /// Tone map the sampled border colors in place
+    pub fn apply(&self, border: &mut [Vec3b]) {
+        for pixel in border.iter_mut() {
+            *pixel = self.map(*pixel);
+        }
+    }
+
+    fn map(&self, pixel: Vec3b) -> Vec3b {
+        // Vec3b holds BGR
+        let bt2020 = [
+            self.pq_to_linear[pixel[2] as usize],
+            self.pq_to_linear[pixel[1] as usize],
+            self.pq_to_linear[pixel[0] as usize],
+        ];
+
+        let mut rgb = [0.0f32; 3];
+        for (value, row) in rgb.iter_mut().zip(BT2020_TO_BT709.iter()) {
+            let linear: f32 = row.iter().zip(bt2020.iter()).map(|(m, c)| m * c).sum();
+            *value = self.tone_curve(linear.max(0.0));
+        }
+
+        // Recover saturation and contrast that got lost in the conversion
+        let luma: f32 = rgb.iter().zip(LUMA.iter()).map(|(c, l)| c * l).sum();
+        for value in rgb.iter_mut() {
+            let saturated = luma + (*value - luma) * self.settings.saturation;
+            let encoded = saturated.clamp(0.0, 1.0).powf(1.0 / SDR_GAMMA);
+            *value = ((encoded - 0.5) * self.settings.contrast + 0.5).clamp(0.0, 1.0);
+        }
+
+        let channel = |value: f32| (value * 255.0).round() as u8;
+        Vec3b::from_array([channel(rgb[2]), channel(rgb[1]), channel(rgb[0])])
+    }
+
+    /// Extended Reinhard curve. Highlights are compressed smoothly and the peak brightness of the
+    /// content is mapped to exactly 1.
+    fn tone_curve(&self, value: f32) -> f32 {
+        let peak = (self.settings.peak_nits / self.settings.white_nits.max(1.0)).max(1.0);
+        value * (1.0 + value / (peak * peak)) / (1.0 + value)
+    }
+}
+
+/// Decodes a PQ signal in the range 0..1 to brightness in nits
+fn pq_eotf(signal: f32) -> f32 {
+    let power = signal.powf(1.0 / PQ_M2);
+    let linear = ((power - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * power)).powf(1.0 / PQ_M1);
+    linear * PQ_PEAK_NITS
+}
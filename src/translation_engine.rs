@@ -4,12 +4,13 @@ use opencv::core::{Mat, MatTrait, MatTraitConst, Rect, Vec3b};
 use smart_leds::RGB8;
 use tracing::debug;
 
-use crate::settings::{Direction, StartCorner};
+use crate::sampling;
+use crate::settings::{Direction, EdgeDepths, EdgeSamplingModes, SamplingMode, StartCorner};
 
 // Roi, Target Mat, Offset
-type Action = Box<dyn Fn(&Mat, &mut Vec<Vec3b>) -> Result<()>>;
+pub type Action = Box<dyn Fn(&Mat, &mut Vec<Vec3b>) -> Result<()>>;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 enum EdgeDirection {
     RTL,
     LTR,
@@ -23,20 +24,24 @@ impl TranslationEngine {
     /// An array with 4 closures will be returned by this function. These 4 closures will
     /// correspons to the 4 edges of a display. The 4 closures will be applied to each incoming
     /// frame and translate the color values to a 1D array that represents the LED strip
+    ///
+    /// Width and height are the size of the whole frame. Each edge is captured as deep as given
+    /// by depths and reduced with the sampling mode given by modes.
     pub fn new(
         start: StartCorner,
         direction: Direction,
         width: i32,
         height: i32,
-        thickness: i32,
+        depths: EdgeDepths,
+        modes: EdgeSamplingModes,
     ) -> [Action; 4] {
         debug!(
-            "Setting up frame translation for start: {:?} direction: {:?} border_thickness: {}",
-            start, direction, thickness
+            "Setting up frame translation for start: {:?} direction: {:?} depths: {:?} modes: {:?}",
+            start, direction, depths, modes
         );
         match direction {
-            Direction::CW => Self::get_translation_funcs_cw(start, width, height, thickness),
-            Direction::CCW => Self::get_translation_funcs_ccw(start, width, height, thickness),
+            Direction::CW => Self::get_translation_funcs_cw(start, width, height, depths, modes),
+            Direction::CCW => Self::get_translation_funcs_ccw(start, width, height, depths, modes),
         }
     }
 
@@ -47,42 +52,51 @@ impl TranslationEngine {
         start: StartCorner,
         width: i32,
         height: i32,
-        thickness: i32,
+        depths: EdgeDepths,
+        modes: EdgeSamplingModes,
     ) -> [Action; 4] {
         debug!(
             "Setting up translation functions for clockwise layout starting from {:?}",
             start
         );
-        let top_region = Rect::new(0, 0, width, thickness);
-        let right_region = Rect::new(width, 0, thickness, height);
-        let bottom_region = Rect::new(thickness, height, width, thickness);
-        let left_region = Rect::new(0, thickness, thickness, height);
+        let EdgeDepths {
+            top,
+            right,
+            bottom,
+            left,
+        } = depths;
+
+        // Each region owns the corner at which it starts when walking clockwise
+        let top_region = Rect::new(0, 0, width - right, top);
+        let right_region = Rect::new(width - right, 0, right, height - bottom);
+        let bottom_region = Rect::new(left, height - bottom, width - left, bottom);
+        let left_region = Rect::new(0, top, left, height - top);
 
         match start {
-            StartCorner::TL => [
-                Self::translation_func(EdgeDirection::LTR, 0, top_region),
-                Self::translation_func(EdgeDirection::TTB, width, right_region),
-                Self::translation_func(EdgeDirection::RTL, width + height, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, width + height + width, left_region),
-            ],
-            StartCorner::TR => [
-                Self::translation_func(EdgeDirection::TTB, 0, right_region),
-                Self::translation_func(EdgeDirection::RTL, height, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, height + width, left_region),
-                Self::translation_func(EdgeDirection::LTR, height + width + height, top_region),
-            ],
-            StartCorner::BR => [
-                Self::translation_func(EdgeDirection::RTL, 0, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, width, left_region),
-                Self::translation_func(EdgeDirection::LTR, width + height, top_region),
-                Self::translation_func(EdgeDirection::TTB, width + height + width, right_region),
-            ],
-            StartCorner::BL => [
-                Self::translation_func(EdgeDirection::BTT, 0, left_region),
-                Self::translation_func(EdgeDirection::LTR, height, top_region),
-                Self::translation_func(EdgeDirection::TTB, height + width, right_region),
-                Self::translation_func(EdgeDirection::RTL, height + width + height, bottom_region),
-            ],
+            StartCorner::TL => Self::chain([
+                (EdgeDirection::LTR, top_region, modes.top),
+                (EdgeDirection::TTB, right_region, modes.right),
+                (EdgeDirection::RTL, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, left_region, modes.left),
+            ]),
+            StartCorner::TR => Self::chain([
+                (EdgeDirection::TTB, right_region, modes.right),
+                (EdgeDirection::RTL, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, left_region, modes.left),
+                (EdgeDirection::LTR, top_region, modes.top),
+            ]),
+            StartCorner::BR => Self::chain([
+                (EdgeDirection::RTL, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, left_region, modes.left),
+                (EdgeDirection::LTR, top_region, modes.top),
+                (EdgeDirection::TTB, right_region, modes.right),
+            ]),
+            StartCorner::BL => Self::chain([
+                (EdgeDirection::BTT, left_region, modes.left),
+                (EdgeDirection::LTR, top_region, modes.top),
+                (EdgeDirection::TTB, right_region, modes.right),
+                (EdgeDirection::RTL, bottom_region, modes.bottom),
+            ]),
         }
     }
 
@@ -93,193 +107,333 @@ impl TranslationEngine {
         start: StartCorner,
         width: i32,
         height: i32,
-        thickness: i32,
+        depths: EdgeDepths,
+        modes: EdgeSamplingModes,
     ) -> [Action; 4] {
         debug!(
             "Setting up translation functions for counter clockwise layout starting from {:?}",
             start
         );
-        let top_region = Rect::new(thickness, 0, width, thickness);
-        let right_region = Rect::new(width, thickness, thickness, height);
-        let bottom_region = Rect::new(0, height, width, thickness);
-        let left_region = Rect::new(0, 0, thickness, height);
+        let EdgeDepths {
+            top,
+            right,
+            bottom,
+            left,
+        } = depths;
+
+        // Each region owns the corner at which it starts when walking counter clockwise
+        let top_region = Rect::new(left, 0, width - left, top);
+        let right_region = Rect::new(width - right, top, right, height - top);
+        let bottom_region = Rect::new(0, height - bottom, width - right, bottom);
+        let left_region = Rect::new(0, 0, left, height - bottom);
 
         match start {
-            StartCorner::TL => [
-                Self::translation_func(EdgeDirection::TTB, 0, left_region),
-                Self::translation_func(EdgeDirection::LTR, height, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, height + width, right_region),
-                Self::translation_func(EdgeDirection::RTL, height + width + height, top_region),
-            ],
-            StartCorner::BL => [
-                Self::translation_func(EdgeDirection::LTR, 0, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, width, right_region),
-                Self::translation_func(EdgeDirection::RTL, width + height, top_region),
-                Self::translation_func(EdgeDirection::TTB, width + height + width, left_region),
-            ],
-            StartCorner::BR => [
-                Self::translation_func(EdgeDirection::BTT, 0, right_region),
-                Self::translation_func(EdgeDirection::RTL, height, top_region),
-                Self::translation_func(EdgeDirection::TTB, height + width, left_region),
-                Self::translation_func(EdgeDirection::LTR, height + width + height, bottom_region),
-            ],
-            StartCorner::TR => [
-                Self::translation_func(EdgeDirection::RTL, 0, top_region),
-                Self::translation_func(EdgeDirection::TTB, width, left_region),
-                Self::translation_func(EdgeDirection::LTR, width + height, bottom_region),
-                Self::translation_func(EdgeDirection::BTT, width + height + width, right_region),
-            ],
+            StartCorner::TL => Self::chain([
+                (EdgeDirection::TTB, left_region, modes.left),
+                (EdgeDirection::LTR, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, right_region, modes.right),
+                (EdgeDirection::RTL, top_region, modes.top),
+            ]),
+            StartCorner::BL => Self::chain([
+                (EdgeDirection::LTR, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, right_region, modes.right),
+                (EdgeDirection::RTL, top_region, modes.top),
+                (EdgeDirection::TTB, left_region, modes.left),
+            ]),
+            StartCorner::BR => Self::chain([
+                (EdgeDirection::BTT, right_region, modes.right),
+                (EdgeDirection::RTL, top_region, modes.top),
+                (EdgeDirection::TTB, left_region, modes.left),
+                (EdgeDirection::LTR, bottom_region, modes.bottom),
+            ]),
+            StartCorner::TR => Self::chain([
+                (EdgeDirection::RTL, top_region, modes.top),
+                (EdgeDirection::TTB, left_region, modes.left),
+                (EdgeDirection::LTR, bottom_region, modes.bottom),
+                (EdgeDirection::BTT, right_region, modes.right),
+            ]),
         }
     }
 
+    /// Creates the translation functions for four edges in the order in which the lightstrip
+    /// passes them. Each edge is sampled with its own mode. The offset of each edge is the summed
+    /// up length of all edges before it.
+    fn chain(edges: [(EdgeDirection, Rect, SamplingMode); 4]) -> [Action; 4] {
+        let mut offset = 0;
+        edges.map(|(direction, region, mode)| {
+            let length = match direction {
+                EdgeDirection::LTR | EdgeDirection::RTL => region.width,
+                EdgeDirection::TTB | EdgeDirection::BTT => region.height,
+            };
+            let func = Self::translation_func(direction, offset, region, mode);
+            offset += length;
+            func
+        })
+    }
+
     /// Returns a closure translation function that will can be applied to an incoming frame. Each
-    /// translation function averages the values in the provided region along the specified
-    /// direction. The resulting values will be written to target starting from an offset.
-    fn translation_func(direction: EdgeDirection, offset: i32, region: Rect) -> Action {
-        debug!("Creating translation func for direction {:?}", direction);
-        match direction {
-            // Read the roi from right to left while calculating the mean and writing to target
-            EdgeDirection::RTL => {
-                Box::new(move |source: &Mat, target: &mut Vec<Vec3b>| -> Result<()> {
-                    let roi = Mat::roi(source, region)?;
-
-                    // Offset + target index will be the actual index of a new value
-                    let mut target_index = 0;
-
-                    // Iterate over the roi from right to left
-                    for col in (0..roi.cols()).rev() {
-                        // Will keep the mean RGB values
-                        let mut mean_b = 0;
-                        let mut mean_g = 0;
-                        let mut mean_r = 0;
-
-                        // Add up the values in one column up
-                        for row in 0..roi.rows() {
-                            let pixel = roi.at_2d::<Vec3b>(row, col)?;
-
-                            mean_b += pixel[0] as u32;
-                            mean_g += pixel[1] as u32;
-                            mean_r += pixel[2] as u32;
+    /// translation function reduces the values in the provided region along the specified
+    /// direction with the given sampling mode. The resulting values will be written to target
+    /// starting from an offset.
+    fn translation_func(
+        direction: EdgeDirection,
+        offset: i32,
+        region: Rect,
+        mode: SamplingMode,
+    ) -> Action {
+        debug!(
+            "Creating translation func for direction {:?} with sampling mode {:?}",
+            direction, mode
+        );
+        Box::new(move |source: &Mat, target: &mut Vec<Vec3b>| -> Result<()> {
+            let roi = Mat::roi(source, region)?;
+
+            // Horizontal edges are read column by column, vertical edges row by row
+            let (lines, line_length) = match direction {
+                EdgeDirection::LTR | EdgeDirection::RTL => (roi.cols(), roi.rows()),
+                EdgeDirection::TTB | EdgeDirection::BTT => (roi.rows(), roi.cols()),
+            };
+
+            // Holds the pixels of the current column or row
+            let mut pixels: Vec<Vec3b> = Vec::with_capacity(line_length as usize);
+
+            // Offset + target index will be the actual index of a new value
+            for target_index in 0..lines {
+                // Right to left and bottom to top read the roi in reverse
+                let line = match direction {
+                    EdgeDirection::LTR | EdgeDirection::TTB => target_index,
+                    EdgeDirection::RTL | EdgeDirection::BTT => lines - 1 - target_index,
+                };
+
+                pixels.clear();
+                for position in 0..line_length {
+                    let pixel = match direction {
+                        EdgeDirection::LTR | EdgeDirection::RTL => {
+                            roi.at_2d::<Vec3b>(position, line)?
                         }
-
-                        // Calculate the mean
-                        mean_b /= roi.rows() as u32;
-                        mean_g /= roi.rows() as u32;
-                        mean_r /= roi.rows() as u32;
-
-                        // Write resulting RGB value to target and increase counter
-                        target[(offset + target_index) as usize] =
-                            Vec3b::from_array([mean_b as u8, mean_g as u8, mean_r as u8]);
-                        target_index += 1;
-                    }
-
-                    Ok(())
-                })
-            }
-            EdgeDirection::LTR => {
-                Box::new(move |source: &Mat, target: &mut Vec<Vec3b>| -> Result<()> {
-                    let roi = Mat::roi(source, region)?;
-
-                    let mut target_index = 0;
-
-                    // Iterate over the roi from right to left
-                    for col in 0..roi.cols() {
-                        // Will keep the mean RGB values
-                        let mut mean_b = 0;
-                        let mut mean_g = 0;
-                        let mut mean_r = 0;
-
-                        for row in 0..roi.rows() {
-                            let pixel = roi.at_2d::<Vec3b>(row, col)?;
-
-                            mean_b += pixel[0] as u32;
-                            mean_g += pixel[1] as u32;
-                            mean_r += pixel[2] as u32;
+                        EdgeDirection::TTB | EdgeDirection::BTT => {
+                            roi.at_2d::<Vec3b>(line, position)?
                         }
+                    };
+                    pixels.push(*pixel);
+                }
 
-                        // Calculate the mean
-                        mean_b /= roi.rows() as u32;
-                        mean_g /= roi.rows() as u32;
-                        mean_r /= roi.rows() as u32;
-
-                        // Write resulting RGB value to target and increase counter
-                        target[(offset + target_index) as usize] =
-                            Vec3b::from_array([mean_b as u8, mean_g as u8, mean_r as u8]);
-                        target_index += 1;
-                    }
-
-                    Ok(())
-                })
+                // Write resulting value to target
+                target[(offset + target_index) as usize] = sampling::sample(mode, &pixels);
             }
-            EdgeDirection::TTB => {
-                Box::new(move |source: &Mat, target: &mut Vec<Vec3b>| -> Result<()> {
-                    let roi = Mat::roi(source, region)?;
-
-                    let mut target_index = 0;
-
-                    // Iterate over the roi from right to left
-                    for row in 0..roi.rows() {
-                        // Will keep the mean RGB values
-                        let mut mean_b = 0;
-                        let mut mean_g = 0;
-                        let mut mean_r = 0;
-
-                        for col in 0..roi.cols() {
-                            let pixel = roi.at_2d::<Vec3b>(row, col)?;
 
-                            mean_b += pixel[0] as u32;
-                            mean_g += pixel[1] as u32;
-                            mean_r += pixel[2] as u32;
-                        }
-
-                        // Calculate the mean
-                        mean_b /= roi.cols() as u32;
-                        mean_g /= roi.cols() as u32;
-                        mean_r /= roi.cols() as u32;
-
-                        // Write resulting RGB value to target and increase counter
-                        target[(offset + target_index) as usize] =
-                            Vec3b::from_array([mean_b as u8, mean_g as u8, mean_r as u8]);
-                        target_index += 1;
-                    }
+            Ok(())
+        })
+    }
+}
 
-                    Ok(())
-                })
+#[cfg(test)]
+mod tests {
+    use opencv::core::{Scalar, CV_8UC3};
+
+    use super::*;
+    use crate::settings::SamplingModes;
+    use crate::{resampler, sampling};
+
+    const CORNERS: [StartCorner; 4] = [
+        StartCorner::TL,
+        StartCorner::TR,
+        StartCorner::BR,
+        StartCorner::BL,
+    ];
+
+    /// Frame in which every pixel holds its own coordinates as blue (x) and green (y)
+    fn coordinate_frame(width: i32, height: i32) -> Mat {
+        let mut frame =
+            Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(0.0)).unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                *frame.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from_array([x as u8, y as u8, 0]);
             }
-            EdgeDirection::BTT => {
-                Box::new(move |source: &Mat, target: &mut Vec<Vec3b>| -> Result<()> {
-                    let roi = Mat::roi(source, region)?;
-
-                    let mut target_index = 0;
-
-                    // Iterate over the roi from right to left
-                    for row in (0..roi.rows()).rev() {
-                        // Will keep the mean RGB values
-                        let mut mean_b = 0;
-                        let mut mean_g = 0;
-                        let mut mean_r = 0;
+        }
+        frame
+    }
 
-                        for col in 0..roi.cols() {
-                            let pixel = roi.at_2d::<Vec3b>(row, col)?;
+    /// Border pixels of a frame in the order in which the lightstrip passes them
+    fn border(start: StartCorner, direction: Direction, width: i32, height: i32) -> Vec<Vec3b> {
+        let depths = EdgeDepths {
+            top: 1,
+            right: 1,
+            bottom: 1,
+            left: 1,
+        };
+        let frame = coordinate_frame(width, height);
+        let mut border = vec![Vec3b::default(); depths.border_length(width, height) as usize];
+        let modes = SamplingModes::default().resolve();
+        for func in TranslationEngine::new(start, direction, width, height, depths, modes) {
+            func(&frame, &mut border).unwrap();
+        }
+        border
+    }
 
-                            mean_b += pixel[0] as u32;
-                            mean_g += pixel[1] as u32;
-                            mean_r += pixel[2] as u32;
-                        }
+    /// The outermost ring of pixels walked from the given corner, computed independently of the
+    /// regions of the translation engine
+    fn expected_walk(
+        start: StartCorner,
+        direction: Direction,
+        width: i32,
+        height: i32,
+    ) -> Vec<(i32, i32)> {
+        // Clockwise from the top left corner
+        let mut walk = Vec::new();
+        walk.extend((0..width - 1).map(|x| (x, 0)));
+        walk.extend((0..height - 1).map(|y| (width - 1, y)));
+        walk.extend((1..width).rev().map(|x| (x, height - 1)));
+        walk.extend((1..height).rev().map(|y| (0, y)));
+
+        let corner = match start {
+            StartCorner::TL => (0, 0),
+            StartCorner::TR => (width - 1, 0),
+            StartCorner::BR => (width - 1, height - 1),
+            StartCorner::BL => (0, height - 1),
+        };
+        let first = walk.iter().position(|pixel| *pixel == corner).unwrap();
+        walk.rotate_left(first);
+        if matches!(direction, Direction::CCW) {
+            // Walking backwards from the same corner
+            walk[1..].reverse();
+        }
+        walk
+    }
 
-                        // Calculate the mean
-                        mean_b /= roi.cols() as u32;
-                        mean_g /= roi.cols() as u32;
-                        mean_r /= roi.cols() as u32;
+    #[test]
+    fn every_border_pixel_is_read_in_walking_order() {
+        let (width, height) = (16, 9);
+        for direction in [Direction::CW, Direction::CCW] {
+            for start in CORNERS {
+                let pixels: Vec<(i32, i32)> = border(start, direction, width, height)
+                    .iter()
+                    .map(|pixel| (pixel[0] as i32, pixel[1] as i32))
+                    .collect();
+                assert_eq!(
+                    pixels,
+                    expected_walk(start, direction, width, height),
+                    "{:?} {:?}",
+                    start,
+                    direction
+                );
+            }
+        }
+    }
 
-                        target[(offset + target_index) as usize] =
-                            Vec3b::from_array([mean_b as u8, mean_g as u8, mean_r as u8]);
-                        target_index += 1;
-                    }
+    #[test]
+    fn every_led_covers_its_edge() {
+        // On a square frame with 4 LEDs every LED covers exactly one edge. The edges are
+        // identified by the mean coordinates of their pixels.
+        let size = 9;
+        let last = (size - 1) as f64;
+        let middle = last / 2.0;
+
+        for direction in [Direction::CW, Direction::CCW] {
+            for start in CORNERS {
+                let leds = resampler::resample(&border(start, direction, size, size), 4);
+                let centers: Vec<(f64, f64)> = leds
+                    .iter()
+                    .map(|led| (led.b as f64 / 257.0, led.g as f64 / 257.0))
+                    .collect();
+
+                // Each edge starts at a corner and excludes the corner it ends at
+                let shift = if matches!(direction, Direction::CW) {
+                    0.5
+                } else {
+                    -0.5
+                };
+                let top = (middle - shift, 0.0);
+                let right = (last, middle - shift);
+                let bottom = (middle + shift, last);
+                let left = (0.0, middle + shift);
+                let edges = match (direction, start) {
+                    (Direction::CW, StartCorner::TL) => [top, right, bottom, left],
+                    (Direction::CW, StartCorner::TR) => [right, bottom, left, top],
+                    (Direction::CW, StartCorner::BR) => [bottom, left, top, right],
+                    (Direction::CW, StartCorner::BL) => [left, top, right, bottom],
+                    (Direction::CCW, StartCorner::TL) => [left, bottom, right, top],
+                    (Direction::CCW, StartCorner::BL) => [bottom, right, top, left],
+                    (Direction::CCW, StartCorner::BR) => [right, top, left, bottom],
+                    (Direction::CCW, StartCorner::TR) => [top, left, bottom, right],
+                };
+
+                for (index, (center, edge)) in centers.iter().zip(edges).enumerate() {
+                    let distance = (center.0 - edge.0).abs() + (center.1 - edge.1).abs();
+                    assert!(
+                        distance < 0.01,
+                        "{:?} {:?}: LED {} at {:?} instead of {:?}",
+                        start,
+                        direction,
+                        index,
+                        center,
+                        edge
+                    );
+                }
+            }
+        }
+    }
 
-                    Ok(())
-                })
+    #[test]
+    fn every_edge_uses_its_own_sampling_mode() {
+        let (width, height, depth) = (12, 8, 3);
+        let depths = EdgeDepths {
+            top: depth,
+            right: depth,
+            bottom: depth,
+            left: depth,
+        };
+        let modes = EdgeSamplingModes {
+            top: SamplingMode::Mean,
+            right: SamplingMode::SquaredMean,
+            bottom: SamplingMode::Median,
+            left: SamplingMode::MostSaturated,
+        };
+
+        // Colors that differ in every row and column so the modes give different results
+        let mut frame =
+            Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(0.0)).unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                *frame.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from_array([
+                    ((x * 37 + y * 91) % 256) as u8,
+                    ((x * 5 + y * 71) % 256) as u8,
+                    ((x * 113 + y * 29) % 256) as u8,
+                ]);
             }
         }
+        let pixel = |x: i32, y: i32| *frame.at_2d::<Vec3b>(y, x).unwrap();
+        let sample = |mode, pixels: Vec<Vec3b>| sampling::sample(mode, &pixels);
+
+        // Clockwise from the top left corner, each edge owns the corner at which it starts
+        let mut expected = Vec::new();
+        for x in 0..width - depth {
+            let line = (0..depth).map(|y| pixel(x, y)).collect();
+            expected.push(sample(modes.top, line));
+        }
+        for y in 0..height - depth {
+            let line = (width - depth..width).map(|x| pixel(x, y)).collect();
+            expected.push(sample(modes.right, line));
+        }
+        for x in (depth..width).rev() {
+            let line = (height - depth..height).map(|y| pixel(x, y)).collect();
+            expected.push(sample(modes.bottom, line));
+        }
+        for y in (depth..height).rev() {
+            let line = (0..depth).map(|x| pixel(x, y)).collect();
+            expected.push(sample(modes.left, line));
+        }
+
+        let translate = |modes| {
+            let mut border = vec![Vec3b::default(); expected.len()];
+            for func in
+                TranslationEngine::new(StartCorner::TL, Direction::CW, width, height, depths, modes)
+            {
+                func(&frame, &mut border).unwrap();
+            }
+            border
+        };
+        assert_eq!(translate(modes), expected);
+        assert_ne!(translate(SamplingModes::default().resolve()), expected);
     }
 }
@@ -1,3 +1,6 @@
+use std::fs;
+use std::io::ErrorKind;
+
 use anyhow::Result;
 use opencv::videoio::{
     VideoCapture, VideoCaptureTrait, CAP_ANY, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH,
@@ -18,6 +21,33 @@ impl Video {
         Ok(input)
     }
 
+    /// Index and name of every video device in /sys/class/video4linux. The index is the value for
+    /// Settings.video_device.
+    pub fn list() -> Result<Vec<(i32, String)>> {
+        let mut devices = Vec::new();
+        let entries = match fs::read_dir("/sys/class/video4linux") {
+            Ok(entries) => entries,
+            // The directory only exists while there is at least one device
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(devices),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(index) = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix("video"))
+                .and_then(|index| index.parse().ok())
+            else {
+                continue;
+            };
+            let name = fs::read_to_string(entry.path().join("name")).unwrap_or_default();
+            devices.push((index, name.trim().to_string()));
+        }
+        devices.sort();
+        Ok(devices)
+    }
+
     /// Attemts to set the resolution at which video will be captured
     fn set_processing_resolution(device: &mut VideoCapture, resolution: (f64, f64)) {
         let _ = device.set(CAP_PROP_FRAME_WIDTH, resolution.0);